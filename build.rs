@@ -30,6 +30,8 @@ fn ensure_icon() {
 }
 
 fn main() {
+    // sqlx::migrate! embeds these files at compile time.
+    println!("cargo:rerun-if-changed=migrations");
     ensure_icon();
     tauri_build::build()
 }
\ No newline at end of file
@@ -0,0 +1,45 @@
+-- Baseline schema. Every statement is idempotent so databases created before
+-- versioned migrations (see Database::adopt_legacy_schema) apply it as a no-op.
+
+CREATE TABLE IF NOT EXISTS focus_sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    start_time DATETIME NOT NULL,
+    end_time DATETIME,
+    duration INTEGER,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS diary_entries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    date DATE NOT NULL,
+    title TEXT,
+    content TEXT NOT NULL,
+    mood INTEGER,
+    images TEXT,
+    encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_diary_entries_date ON diary_entries(date);
+
+CREATE TABLE IF NOT EXISTS todos (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL,
+    description TEXT,
+    completed BOOLEAN DEFAULT FALSE,
+    priority INTEGER DEFAULT 0,
+    due_date DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS alarms (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time TIME NOT NULL,
+    days TEXT,
+    enabled BOOLEAN DEFAULT TRUE,
+    label TEXT,
+    sound_path TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
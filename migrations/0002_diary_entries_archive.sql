@@ -0,0 +1,16 @@
+-- Diary rows folded into another entry are copied here before being removed,
+-- so de-duplication never loses what the user wrote.
+CREATE TABLE IF NOT EXISTS diary_entries_archive (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    original_id INTEGER NOT NULL,
+    merged_into INTEGER,
+    date DATE NOT NULL,
+    title TEXT,
+    content TEXT NOT NULL,
+    mood INTEGER,
+    images TEXT,
+    encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DATETIME,
+    updated_at DATETIME,
+    archived_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
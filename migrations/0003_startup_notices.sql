@@ -0,0 +1,8 @@
+-- Messages produced by background maintenance that the UI shows once at startup.
+CREATE TABLE IF NOT EXISTS startup_notices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    dismissed_at DATETIME
+);
@@ -0,0 +1,6 @@
+-- Profile-level preferences stored as JSON values keyed by name.
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
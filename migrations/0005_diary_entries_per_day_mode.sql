@@ -0,0 +1,4 @@
+-- Whether a date may hold several diary entries is now the `diary_mode`
+-- setting, enforced by save_diary_entry, so the unique index goes away.
+DROP INDEX IF EXISTS idx_diary_entries_date;
+CREATE INDEX IF NOT EXISTS idx_diary_entries_date_created ON diary_entries(date, created_at);
@@ -0,0 +1,2 @@
+-- One-off alarms ring once on this date instead of repeating on `days`.
+ALTER TABLE alarms ADD COLUMN date DATE;
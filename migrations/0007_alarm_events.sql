@@ -0,0 +1,16 @@
+-- Per-alarm snooze overrides; NULL falls back to the `alarm_snooze_defaults` setting.
+ALTER TABLE alarms ADD COLUMN snooze_minutes INTEGER;
+ALTER TABLE alarms ADD COLUMN max_snoozes INTEGER;
+
+-- What happened each time an alarm went off: kind is one of
+-- fired, snoozed, dismissed, missed.
+CREATE TABLE IF NOT EXISTS alarm_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    alarm_id INTEGER NOT NULL REFERENCES alarms(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    scheduled_for DATETIME NOT NULL,
+    occurred_at DATETIME NOT NULL,
+    snoozed_until DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS idx_alarm_events_alarm ON alarm_events(alarm_id, occurred_at);
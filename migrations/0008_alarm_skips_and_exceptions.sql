@@ -0,0 +1,4 @@
+-- The one occurrence of a recurring alarm the user chose to skip.
+ALTER TABLE alarms ADD COLUMN skipped_occurrence DATETIME;
+-- JSON array of inclusive {"start", "end"} date ranges during which the alarm stays quiet.
+ALTER TABLE alarms ADD COLUMN exceptions TEXT;
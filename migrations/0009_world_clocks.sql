@@ -0,0 +1,8 @@
+-- Time zones shown in the world clock, in display order.
+CREATE TABLE IF NOT EXISTS world_clocks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time_zone TEXT NOT NULL,
+    label TEXT,
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
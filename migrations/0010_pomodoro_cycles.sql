@@ -0,0 +1,18 @@
+-- A run of Pomodoro phases: work and short breaks, ending with a long break.
+-- The lengths are copied from the settings when the cycle starts.
+CREATE TABLE IF NOT EXISTS pomodoro_cycles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at DATETIME NOT NULL,
+    ended_at DATETIME,
+    work_minutes INTEGER NOT NULL,
+    short_break_minutes INTEGER NOT NULL,
+    long_break_minutes INTEGER NOT NULL,
+    cycles_before_long_break INTEGER NOT NULL
+);
+
+-- Each phase of a cycle is a focus session; both are NULL for sessions
+-- started outside the Pomodoro timer. phase is one of work, short_break, long_break.
+ALTER TABLE focus_sessions ADD COLUMN cycle_id INTEGER REFERENCES pomodoro_cycles(id) ON DELETE CASCADE;
+ALTER TABLE focus_sessions ADD COLUMN phase TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_focus_sessions_cycle ON focus_sessions(cycle_id);
@@ -0,0 +1,13 @@
+-- Interruptions of a focus session; resumed_at is NULL while paused.
+CREATE TABLE IF NOT EXISTS focus_session_pauses (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id INTEGER NOT NULL REFERENCES focus_sessions(id) ON DELETE CASCADE,
+    paused_at DATETIME NOT NULL,
+    resumed_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS idx_focus_session_pauses_session ON focus_session_pauses(session_id);
+
+-- Seconds spent paused, set when the session ends; duration is now net of it.
+ALTER TABLE focus_sessions ADD COLUMN paused_duration INTEGER;
+UPDATE focus_sessions SET paused_duration = 0 WHERE end_time IS NOT NULL;
@@ -0,0 +1,6 @@
+-- Written periodically while a session runs, so a session left open by a
+-- crash can be closed at the last moment the app was known to be running.
+ALTER TABLE focus_sessions ADD COLUMN last_heartbeat DATETIME;
+-- Open sessions found at startup without a heartbeat, waiting for the user
+-- to say when they ended.
+ALTER TABLE focus_sessions ADD COLUMN orphaned BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,9 @@
+-- At most one session may run at a time. Older versions allowed several, so
+-- all but the newest open one are flagged as orphaned for the user to resolve.
+UPDATE focus_sessions SET orphaned = TRUE
+WHERE end_time IS NULL AND orphaned = FALSE
+  AND id <> (SELECT MAX(id) FROM focus_sessions WHERE end_time IS NULL AND orphaned = FALSE);
+
+-- `orphaned` is the same in every indexed row, so the index admits only one.
+CREATE UNIQUE INDEX IF NOT EXISTS idx_focus_sessions_active ON focus_sessions(orphaned)
+WHERE end_time IS NULL AND orphaned = FALSE;
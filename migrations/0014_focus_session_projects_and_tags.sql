@@ -0,0 +1,13 @@
+-- What a focus session was spent on.
+ALTER TABLE focus_sessions ADD COLUMN project TEXT;
+ALTER TABLE focus_sessions ADD COLUMN todo_id INTEGER REFERENCES todos(id) ON DELETE SET NULL;
+
+CREATE TABLE IF NOT EXISTS focus_session_tags (
+    session_id INTEGER NOT NULL REFERENCES focus_sessions(id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (session_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_focus_session_tags_tag ON focus_session_tags(tag);
+CREATE INDEX IF NOT EXISTS idx_focus_sessions_project ON focus_sessions(project);
+CREATE INDEX IF NOT EXISTS idx_focus_sessions_todo ON focus_sessions(todo_id);
@@ -0,0 +1,34 @@
+-- One entry per day is enforced by the schema again, now only while the
+-- `diary_mode` setting asks for it. A missing setting means one per day.
+CREATE TRIGGER IF NOT EXISTS diary_entries_one_per_day_insert
+BEFORE INSERT ON diary_entries
+WHEN COALESCE((SELECT value FROM settings WHERE key = 'diary_mode'), '"one_per_day"') = '"one_per_day"'
+ AND EXISTS (SELECT 1 FROM diary_entries WHERE date = NEW.date)
+BEGIN
+    SELECT RAISE(ABORT, 'A diary entry already exists for this date');
+END;
+
+CREATE TRIGGER IF NOT EXISTS diary_entries_one_per_day_update
+BEFORE UPDATE OF date ON diary_entries
+WHEN COALESCE((SELECT value FROM settings WHERE key = 'diary_mode'), '"one_per_day"') = '"one_per_day"'
+ AND EXISTS (SELECT 1 FROM diary_entries WHERE date = NEW.date AND id <> NEW.id)
+BEGIN
+    SELECT RAISE(ABORT, 'A diary entry already exists for this date');
+END;
+
+-- Switching to one per day is only allowed once same-date entries are merged.
+CREATE TRIGGER IF NOT EXISTS settings_diary_mode_insert
+BEFORE INSERT ON settings
+WHEN NEW.key = 'diary_mode' AND NEW.value = '"one_per_day"'
+ AND EXISTS (SELECT 1 FROM diary_entries GROUP BY date HAVING COUNT(*) > 1)
+BEGIN
+    SELECT RAISE(ABORT, 'Merge same-date diary entries before switching to one entry per day');
+END;
+
+CREATE TRIGGER IF NOT EXISTS settings_diary_mode_update
+BEFORE UPDATE OF value ON settings
+WHEN NEW.key = 'diary_mode' AND NEW.value = '"one_per_day"'
+ AND EXISTS (SELECT 1 FROM diary_entries GROUP BY date HAVING COUNT(*) > 1)
+BEGIN
+    SELECT RAISE(ABORT, 'Merge same-date diary entries before switching to one entry per day');
+END;
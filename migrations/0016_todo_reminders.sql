@@ -0,0 +1,6 @@
+-- When the due reminder for a todo was shown; cleared when the due date changes.
+ALTER TABLE todos ADD COLUMN reminded_at DATETIME;
+
+-- Todos already overdue on upgrade are not worth a burst of reminders.
+UPDATE todos SET reminded_at = CURRENT_TIMESTAMP
+WHERE due_date IS NOT NULL AND datetime(due_date) <= datetime('now');
@@ -0,0 +1,693 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Datelike, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc, Weekday};
+use anyhow::Result;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
+use tauri::{AppHandle, Emitter};
+use sqlx::SqlitePool;
+use tokio::sync::{Mutex, Notify};
+use crate::database::Database;
+use crate::models::{
+    Alarm, AlarmDay, AlarmEventKind, AlarmFired, AlarmOccurrence, AlarmSchedule, DateRange, SnoozeDefaults,
+};
+use crate::notifications::{NotificationKind, NotificationService};
+
+/// Emitted when an alarm goes off, with an [`AlarmFired`] payload.
+pub const ALARM_FIRED_EVENT: &str = "alarm-fired";
+
+/// The scheduler re-reads the alarms at least this often, so clock changes
+/// and suspend/resume are noticed without a dedicated signal.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Emitted with the alarm id when a ringing alarm was never snoozed or dismissed.
+pub const ALARM_MISSED_EVENT: &str = "alarm-missed";
+
+pub const ALARM_SNOOZE_DEFAULTS: &str = "alarm_snooze_defaults";
+
+pub const MAX_SNOOZE_MINUTES: u32 = 120;
+
+/// How long an alarm keeps ringing before it counts as missed.
+const RING_TIMEOUT: chrono::Duration = chrono::Duration::minutes(10);
+
+/// An alarm found late, after a suspend or restart, still rings if it is at
+/// most this late; otherwise it is logged as missed.
+const CATCH_UP_GRACE: chrono::Duration = chrono::Duration::minutes(15);
+
+/// Bounds the catch-up after the app was closed for a long time.
+const MAX_MISSED_PER_ALARM: usize = 50;
+
+/// Persisted outside the database, since writing it there every minute would
+/// make every backup run see a change.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AlarmState {
+    checked_until: Option<DateTime<Utc>>,
+}
+
+/// An alarm that went off and has not been dismissed yet.
+#[derive(Debug, Clone)]
+struct Ringing {
+    alarm: Alarm,
+    scheduled_for: DateTime<Local>,
+    /// When it last started ringing, either on schedule or after a snooze.
+    rang_at: DateTime<Local>,
+    snoozes: u32,
+    snoozed_until: Option<DateTime<Local>>,
+}
+
+impl Ringing {
+    fn new(alarm: Alarm, scheduled_for: DateTime<Local>, rang_at: DateTime<Local>) -> Self {
+        Self { alarm, scheduled_for, rang_at, snoozes: 0, snoozed_until: None }
+    }
+}
+
+pub struct AlarmScheduler {
+    changed: Notify,
+    ringing: std::sync::Mutex<HashMap<i64, Ringing>>,
+    state_path: PathBuf,
+}
+
+impl AlarmScheduler {
+    pub fn new() -> Result<Self> {
+        let proj_dirs = ProjectDirs::from("com", "productivityapp", "app")
+            .ok_or_else(|| anyhow::anyhow!("Failed to get project directories"))?;
+        fs::create_dir_all(proj_dirs.data_dir())?;
+
+        Ok(Self {
+            changed: Notify::new(),
+            ringing: std::sync::Mutex::new(HashMap::new()),
+            state_path: proj_dirs.data_dir().join("alarm_state.json"),
+        })
+    }
+
+    /// Up to when alarms were handled in the previous run, if there was one.
+    fn load_checked_until(&self) -> Option<DateTime<Local>> {
+        let state: AlarmState = fs::read(&self.state_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())?;
+        state.checked_until.map(|t| t.with_timezone(&Local))
+    }
+
+    fn save_checked_until(&self, checked_until: DateTime<Local>) -> Result<()> {
+        let state = AlarmState { checked_until: Some(checked_until.with_timezone(&Utc)) };
+        fs::write(&self.state_path, serde_json::to_vec_pretty(&state)?)?;
+        Ok(())
+    }
+
+    /// Call after the `alarms` table changes so the next fire time is recomputed.
+    pub fn notify_changed(&self) {
+        self.changed.notify_one();
+    }
+}
+
+/// Upper bound for `get_next_alarm_occurrences`.
+pub const MAX_OCCURRENCES: usize = 500;
+
+/// Returned by the alarm commands. Serialized as `{ kind, message }` so the
+/// frontend can point at the offending field.
+#[derive(Debug, thiserror::Error)]
+pub enum AlarmError {
+    #[error("Invalid alarm time: {0} (expected HH:MM)")]
+    InvalidTime(String),
+    #[error("Invalid alarm day: {0}")]
+    InvalidDay(String),
+    #[error("Invalid alarm date: {0} (expected YYYY-MM-DD)")]
+    InvalidDate(String),
+    #[error("A one-off alarm cannot also repeat on weekdays")]
+    DateWithDays,
+    #[error("Alarm date {0} is in the past")]
+    DateInPast(NaiveDate),
+    #[error("Exception range starts after it ends: {0} to {1}")]
+    InvalidDateRange(NaiveDate, NaiveDate),
+    #[error("Alarm {0} not found")]
+    NotFound(i64),
+    #[error("Alarm {0} has no upcoming occurrence to skip")]
+    NoUpcomingOccurrence(i64),
+    #[error("Alarm {0} is not ringing")]
+    NotRinging(i64),
+    #[error("Snooze limit of {0} reached; dismiss the alarm instead")]
+    SnoozeLimitReached(u32),
+    #[error("Snooze length must be between 1 and {max} minutes, not {0}", max = MAX_SNOOZE_MINUTES)]
+    InvalidSnoozeLength(u32),
+    #[error("{0}")]
+    Storage(String),
+}
+
+impl AlarmError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AlarmError::InvalidTime(_) => "invalid_time",
+            AlarmError::InvalidDay(_) => "invalid_day",
+            AlarmError::InvalidDate(_) => "invalid_date",
+            AlarmError::DateWithDays => "date_with_days",
+            AlarmError::DateInPast(_) => "date_in_past",
+            AlarmError::InvalidDateRange(..) => "invalid_date_range",
+            AlarmError::NotFound(_) => "not_found",
+            AlarmError::NoUpcomingOccurrence(_) => "no_upcoming_occurrence",
+            AlarmError::NotRinging(_) => "not_ringing",
+            AlarmError::SnoozeLimitReached(_) => "snooze_limit_reached",
+            AlarmError::InvalidSnoozeLength(_) => "invalid_snooze_length",
+            AlarmError::Storage(_) => "storage",
+        }
+    }
+}
+
+impl Serialize for AlarmError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("AlarmError", 2)?;
+        error.serialize_field("kind", self.kind())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
+    }
+}
+
+impl From<sqlx::Error> for AlarmError {
+    fn from(e: sqlx::Error) -> Self {
+        AlarmError::Storage(e.to_string())
+    }
+}
+
+/// Lets `?` pass through the `String` errors of the shared helpers, such as the locked error.
+impl From<String> for AlarmError {
+    fn from(message: String) -> Self {
+        AlarmError::Storage(message)
+    }
+}
+
+/// Accepts weekday names ("mon", "Monday") and JavaScript day numbers ("0" is Sunday).
+fn parse_day(day: &str) -> Result<AlarmDay, AlarmError> {
+    match day.parse::<u8>() {
+        Ok(n) if n < 7 => Ok(Weekday::try_from((n + 6) % 7).expect("in range").into()),
+        Ok(_) => Err(AlarmError::InvalidDay(day.to_string())),
+        Err(_) => Weekday::from_str(day)
+            .map(AlarmDay::from)
+            .map_err(|_| AlarmError::InvalidDay(day.to_string())),
+    }
+}
+
+impl AlarmSchedule {
+    /// Validates user input. `time` is `HH:MM` or `HH:MM:SS`.
+    pub fn parse(time: &str, days: &[String], date: Option<&str>) -> Result<Self, AlarmError> {
+        let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+            .map_err(|_| AlarmError::InvalidTime(time.to_string()))?;
+        let days = days.iter()
+            .map(|day| parse_day(day))
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        let date = date
+            .map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| AlarmError::InvalidDate(date.to_string())))
+            .transpose()?;
+        if date.is_some() && !days.is_empty() {
+            return Err(AlarmError::DateWithDays);
+        }
+        Ok(Self { time, days, date, exceptions: Vec::new(), skipped: None })
+    }
+
+    pub fn set_exceptions(&mut self, exceptions: Vec<DateRange>) -> Result<(), AlarmError> {
+        if let Some(range) = exceptions.iter().find(|range| range.start > range.end) {
+            return Err(AlarmError::InvalidDateRange(range.start, range.end));
+        }
+        self.exceptions = exceptions;
+        Ok(())
+    }
+
+    /// Like [`AlarmSchedule::parse`], for a new or changed alarm that must
+    /// still be able to ring.
+    pub fn parse_upcoming(time: &str, days: &[String], date: Option<&str>) -> Result<Self, AlarmError> {
+        let schedule = Self::parse(time, days, date)?;
+        if let Some(date) = schedule.date {
+            if schedule.next_after(&Local, Local::now()).is_none() {
+                return Err(AlarmError::DateInPast(date));
+            }
+        }
+        Ok(schedule)
+    }
+
+    /// The form stored in the `time` column.
+    pub fn time_string(&self) -> String {
+        if self.time.format("%S").to_string() == "00" {
+            self.time.format("%H:%M").to_string()
+        } else {
+            self.time.format("%H:%M:%S").to_string()
+        }
+    }
+
+    /// The form stored in the `days` column; `None` when the alarm is one-off
+    /// or rings every day.
+    pub fn days_json(&self) -> Option<String> {
+        if self.days.is_empty() {
+            return None;
+        }
+        Some(serde_json::to_string(&self.days).expect("weekday names serialize"))
+    }
+
+    /// The form stored in the `exceptions` column; `None` when there are none.
+    pub fn exceptions_json(&self) -> Option<String> {
+        if self.exceptions.is_empty() {
+            return None;
+        }
+        Some(serde_json::to_string(&self.exceptions).expect("date ranges serialize"))
+    }
+
+    /// The first time strictly after `after` that the alarm rings in `tz`.
+    /// When the alarm time is skipped by a DST change it rings as soon as the
+    /// clocks have jumped past it; when it occurs twice it rings the first time.
+    /// Dates inside an exception and the skipped occurrence are passed over.
+    pub fn next_after<Tz: TimeZone>(&self, tz: &Tz, after: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let mut date = self.date.unwrap_or_else(|| after.with_timezone(tz).date_naive());
+        // Fifteen days covers a weekly alarm whose time today has already
+        // passed and whose next occurrence is skipped. Excepted days don't count.
+        let mut days_left = 15;
+        while days_left > 0 {
+            if let Some(range) = self.exceptions.iter().find(|range| range.contains(date)) {
+                if self.date.is_some() {
+                    return None;
+                }
+                date = range.end.succ_opt()?;
+                continue;
+            }
+            days_left -= 1;
+            if self.days.is_empty() || self.days.contains(&date.weekday().into()) {
+                let at = resolve_local(tz, date.and_time(self.time))
+                    .filter(|at| *at > after && self.skipped != Some(at.with_timezone(&Utc)));
+                if at.is_some() {
+                    return at;
+                }
+            }
+            if self.date.is_some() {
+                return None;
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+/// Maps a wall-clock time onto `tz`, moving times in a DST gap forward to the
+/// first minute that exists.
+fn resolve_local<Tz: TimeZone>(tz: &Tz, naive: NaiveDateTime) -> Option<DateTime<Tz>> {
+    let mut candidate = naive;
+    // No time zone skips more than a day.
+    for _ in 0..=24 * 60 {
+        match tz.from_local_datetime(&candidate) {
+            LocalResult::Single(at) => return Some(at),
+            // Not necessarily in order for `Local`, so compare the instants.
+            LocalResult::Ambiguous(a, b) => return Some(a.min(b)),
+            LocalResult::None => {
+                candidate = (candidate + chrono::Duration::minutes(1)).with_second(0)?;
+            }
+        }
+    }
+    None
+}
+
+impl Alarm {
+    /// The stored schedule. Rows written before validation existed may not parse.
+    pub fn schedule(&self) -> Result<AlarmSchedule, AlarmError> {
+        let days: Vec<String> = match self.days.as_deref() {
+            Some(days) => serde_json::from_str(days).map_err(|_| AlarmError::InvalidDay(days.to_string()))?,
+            None => Vec::new(),
+        };
+        let mut schedule = AlarmSchedule::parse(&self.time, &days, None)?;
+        schedule.date = self.date;
+        schedule.skipped = self.skipped_occurrence;
+        if let Some(exceptions) = self.exceptions.as_deref() {
+            schedule.exceptions = serde_json::from_str(exceptions)
+                .map_err(|_| AlarmError::Storage(format!("Invalid alarm exceptions: {}", exceptions)))?;
+        }
+        Ok(schedule)
+    }
+}
+
+/// Upcoming fire times across all enabled alarms, soonest first.
+pub fn upcoming_occurrences<Tz: TimeZone>(
+    alarms: &[Alarm],
+    tz: &Tz,
+    from: DateTime<Tz>,
+    count: usize,
+) -> Vec<AlarmOccurrence> {
+    let count = count.min(MAX_OCCURRENCES);
+    let mut occurrences = Vec::new();
+    for alarm in alarms.iter().filter(|alarm| alarm.enabled) {
+        let Ok(schedule) = alarm.schedule() else {
+            continue;
+        };
+        let mut after = from.clone();
+        for _ in 0..count {
+            let Some(at) = schedule.next_after(tz, after) else {
+                break;
+            };
+            occurrences.push(AlarmOccurrence {
+                alarm_id: alarm.id,
+                label: alarm.label.clone(),
+                at: at.fixed_offset(),
+            });
+            after = at;
+        }
+    }
+    occurrences.sort_by_key(|occurrence| occurrence.at);
+    occurrences.truncate(count);
+    occurrences
+}
+
+async fn all_alarms(db: &Mutex<Database>) -> Result<Vec<Alarm>> {
+    let db = db.lock().await;
+    let alarms = sqlx::query_as::<_, Alarm>("SELECT * FROM alarms")
+        .fetch_all(db.pool())
+        .await?;
+    Ok(alarms)
+}
+
+pub async fn record_event(
+    pool: &SqlitePool,
+    alarm_id: i64,
+    kind: AlarmEventKind,
+    scheduled_for: DateTime<Local>,
+    snoozed_until: Option<DateTime<Local>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO alarm_events (alarm_id, kind, scheduled_for, occurred_at, snoozed_until) VALUES (?, ?, ?, ?, ?)")
+        .bind(alarm_id)
+        .bind(kind)
+        .bind(scheduled_for.with_timezone(&Utc))
+        .bind(Utc::now())
+        .bind(snoozed_until.map(|t| t.with_timezone(&Utc)))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+impl AlarmScheduler {
+    /// Puts a ringing alarm to sleep for `minutes`, or the alarm's own or the
+    /// app-wide snooze length. Returns when it will ring again.
+    pub async fn snooze(&self, db: &Database, id: i64, minutes: Option<u32>) -> Result<DateTime<Utc>, AlarmError> {
+        let alarm = sqlx::query_as::<_, Alarm>("SELECT * FROM alarms WHERE id = ?")
+            .bind(id)
+            .fetch_optional(db.pool())
+            .await?
+            .ok_or(AlarmError::NotFound(id))?;
+        let defaults: SnoozeDefaults = Database::get_setting(db.pool(), ALARM_SNOOZE_DEFAULTS)
+            .await
+            .map_err(|e| AlarmError::Storage(e.to_string()))?
+            .unwrap_or_default();
+        let minutes = minutes.or(alarm.snooze_minutes).unwrap_or(defaults.snooze_minutes);
+        validate_snooze_minutes(minutes)?;
+        let max_snoozes = alarm.max_snoozes.unwrap_or(defaults.max_snoozes);
+
+        let (scheduled_for, until) = {
+            let mut ringing = self.ringing.lock().unwrap();
+            let entry = ringing.get_mut(&id)
+                .filter(|entry| entry.snoozed_until.is_none())
+                .ok_or(AlarmError::NotRinging(id))?;
+            if entry.snoozes >= max_snoozes {
+                return Err(AlarmError::SnoozeLimitReached(max_snoozes));
+            }
+            let until = Local::now() + chrono::Duration::minutes(i64::from(minutes));
+            entry.snoozes += 1;
+            entry.snoozed_until = Some(until);
+            (entry.scheduled_for, until)
+        };
+        record_event(db.pool(), id, AlarmEventKind::Snoozed, scheduled_for, Some(until)).await?;
+        self.notify_changed();
+        Ok(until.with_timezone(&Utc))
+    }
+
+    /// Stops a ringing or snoozed alarm for good.
+    pub async fn dismiss(&self, db: &Database, id: i64) -> Result<(), AlarmError> {
+        let entry = self.ringing.lock().unwrap()
+            .remove(&id)
+            .ok_or(AlarmError::NotRinging(id))?;
+        record_event(db.pool(), id, AlarmEventKind::Dismissed, entry.scheduled_for, None).await?;
+        self.notify_changed();
+        Ok(())
+    }
+}
+
+pub fn validate_snooze_minutes(minutes: u32) -> Result<(), AlarmError> {
+    if (1..=MAX_SNOOZE_MINUTES).contains(&minutes) {
+        Ok(())
+    } else {
+        Err(AlarmError::InvalidSnoozeLength(minutes))
+    }
+}
+
+fn ring(app: &AppHandle, notifications: &NotificationService, alarm: Alarm, scheduled_for: DateTime<Local>, snoozes: u32) {
+    let title = alarm.label.clone().filter(|label| !label.is_empty()).unwrap_or_else(|| "Alarm".to_string());
+    let _ = notifications.notify(NotificationKind::Alarm, title, scheduled_for.format("%H:%M").to_string());
+    let _ = app.emit(ALARM_FIRED_EVENT, AlarmFired {
+        alarm,
+        scheduled_for: scheduled_for.with_timezone(&Utc),
+        snoozes,
+    });
+}
+
+/// "You missed 2 alarms: Wake up at 07:00 on Mon 12 Oct, ..."
+fn missed_message(missed: &[(Alarm, DateTime<Local>)]) -> String {
+    let mut missed: Vec<_> = missed.iter().collect();
+    missed.sort_by_key(|(_, due)| *due);
+    let shown: Vec<String> = missed.iter()
+        .rev()
+        .take(3)
+        .rev()
+        .map(|(alarm, due)| {
+            let label = alarm.label.as_deref().filter(|label| !label.is_empty()).unwrap_or("Alarm");
+            format!("{} at {}", label, due.format("%H:%M on %a %-d %b"))
+        })
+        .collect();
+    let more = missed.len() - shown.len();
+    let mut message = format!(
+        "You missed {} alarm{}: {}",
+        missed.len(),
+        if missed.len() == 1 { "" } else { "s" },
+        shown.join(", "),
+    );
+    if more > 0 {
+        message.push_str(&format!(" and {} earlier", more));
+    }
+    message
+}
+
+/// Runs for the lifetime of the app. Alarms ring even while the app is locked:
+/// the `alarms` table holds nothing encrypted, and a silent alarm clock is worse.
+pub async fn run_schedule(
+    scheduler: Arc<AlarmScheduler>,
+    db: Arc<Mutex<Database>>,
+    notifications: Arc<NotificationService>,
+    app: AppHandle,
+) {
+    // Everything up to here has been handled, including in earlier runs, so
+    // alarms due while the app was closed are caught up on below.
+    let mut checked_until = scheduler.load_checked_until().unwrap_or_else(Local::now);
+    loop {
+        let now = Local::now();
+        if now < checked_until {
+            // The clock was set back; an alarm ringing twice beats one skipped.
+            checked_until = now;
+        }
+        let alarms = all_alarms(&db).await.unwrap_or_default();
+        // (alarm id, kind, scheduled for) to write once the ringing map is released.
+        let mut events = Vec::new();
+        let mut missed = Vec::new();
+        // One-off alarms that have had their turn.
+        let mut finished = Vec::new();
+
+        let mut next_due: Option<DateTime<Local>> = None;
+        {
+            let mut ringing = scheduler.ringing.lock().unwrap();
+            // Alarms disabled or deleted while ringing stop quietly. One-off
+            // alarms are disabled by the scheduler itself when they ring, so
+            // they keep ringing until snoozed away or dismissed.
+            ringing.retain(|id, _| alarms.iter().any(|alarm| {
+                alarm.id == *id && (alarm.enabled || alarm.date.is_some())
+            }));
+            ringing.retain(|id, entry| match entry.snoozed_until {
+                Some(until) if until <= now => {
+                    entry.snoozed_until = None;
+                    entry.rang_at = now;
+                    ring(&app, &notifications, entry.alarm.clone(), entry.scheduled_for, entry.snoozes);
+                    events.push((*id, AlarmEventKind::Fired, entry.scheduled_for));
+                    true
+                }
+                Some(_) => true,
+                None if entry.rang_at + RING_TIMEOUT <= now => {
+                    let _ = app.emit(ALARM_MISSED_EVENT, entry.alarm.id);
+                    events.push((*id, AlarmEventKind::Missed, entry.scheduled_for));
+                    false
+                }
+                None => true,
+            });
+
+            for alarm in alarms.iter().filter(|alarm| alarm.enabled) {
+                // A malformed alarm is skipped rather than stalling the others.
+                let Ok(schedule) = alarm.schedule() else {
+                    continue;
+                };
+                // Normally at most one time is due here. More means the clock
+                // jumped forward (suspend, or the app was closed): only the
+                // latest rings, and only if it is recent enough.
+                let mut due_times = Vec::new();
+                let mut after = checked_until;
+                while let Some(due) = schedule.next_after(&Local, after).filter(|due| *due <= now) {
+                    due_times.push(due);
+                    after = due;
+                    if due_times.len() > MAX_MISSED_PER_ALARM {
+                        due_times.remove(0);
+                    }
+                }
+                if let Some(latest) = due_times.pop() {
+                    if alarm.date.is_some() {
+                        finished.push(alarm.id);
+                    }
+                    missed.extend(due_times.into_iter().map(|due| (alarm.clone(), due)));
+                    if now - latest <= CATCH_UP_GRACE {
+                        if let Some(previous) = ringing.insert(alarm.id, Ringing::new(alarm.clone(), latest, now)) {
+                            events.push((alarm.id, AlarmEventKind::Missed, previous.scheduled_for));
+                        }
+                        ring(&app, &notifications, alarm.clone(), latest, 0);
+                        events.push((alarm.id, AlarmEventKind::Fired, latest));
+                    } else {
+                        missed.push((alarm.clone(), latest));
+                    }
+                }
+                if let Some(next) = schedule.next_after(&Local, now) {
+                    next_due = Some(next_due.map_or(next, |d| d.min(next)));
+                }
+            }
+
+            for entry in ringing.values() {
+                let wake = entry.snoozed_until.unwrap_or(entry.rang_at + RING_TIMEOUT);
+                next_due = Some(next_due.map_or(wake, |d| d.min(wake)));
+            }
+        }
+        checked_until = now;
+        let _ = scheduler.save_checked_until(checked_until);
+
+        events.extend(missed.iter().map(|(alarm, due)| (alarm.id, AlarmEventKind::Missed, *due)));
+        if !events.is_empty() || !finished.is_empty() {
+            let db = db.lock().await;
+            for alarm_id in finished {
+                let _ = sqlx::query("UPDATE alarms SET enabled = FALSE WHERE id = ?")
+                    .bind(alarm_id)
+                    .execute(db.pool())
+                    .await;
+            }
+            for (alarm_id, kind, scheduled_for) in events {
+                let _ = record_event(db.pool(), alarm_id, kind, scheduled_for, None).await;
+            }
+            if !missed.is_empty() {
+                let message = missed_message(&missed);
+                if let Ok(mut conn) = db.pool().acquire().await {
+                    let _ = Database::add_startup_notice(&mut conn, "missed_alarms", &message).await;
+                }
+                let _ = notifications.notify(NotificationKind::Alarm, "Missed alarms", message);
+            }
+        }
+
+        let wait = next_due
+            .map(|due| (due - Local::now()).to_std().unwrap_or_default())
+            .unwrap_or(MAX_SLEEP)
+            .min(MAX_SLEEP);
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = scheduler.changed.notified() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+    use chrono_tz::Tz;
+
+    fn schedule(time: &str, days: &[&str], date: Option<&str>) -> AlarmSchedule {
+        let days: Vec<String> = days.iter().map(|day| day.to_string()).collect();
+        AlarmSchedule::parse(time, &days, date).unwrap()
+    }
+
+    fn new_york(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Tz> {
+        New_York.with_ymd_and_hms(y, m, d, h, min, 0).earliest().unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    fn next(schedule: &AlarmSchedule, after: DateTime<Tz>) -> Option<DateTime<Utc>> {
+        schedule.next_after(&New_York, after).map(|at| at.with_timezone(&Utc))
+    }
+
+    fn range(start: &str, end: &str) -> DateRange {
+        DateRange { start: start.parse().unwrap(), end: end.parse().unwrap() }
+    }
+
+    #[test]
+    fn rings_after_the_spring_forward_gap() {
+        // 2024-03-10 02:00 EST jumps to 03:00 EDT.
+        let daily = schedule("02:30", &[], None);
+        let first = next(&daily, new_york(2024, 3, 9, 12, 0)).unwrap();
+        assert_eq!(first, utc(2024, 3, 10, 7, 0));
+        let second = next(&daily, first.with_timezone(&New_York)).unwrap();
+        assert_eq!(second, utc(2024, 3, 11, 6, 30));
+    }
+
+    #[test]
+    fn rings_once_when_fall_back_repeats_the_time() {
+        // 2024-11-03 02:00 EDT falls back to 01:00 EST, so 01:30 happens twice.
+        let daily = schedule("01:30", &[], None);
+        let first = next(&daily, new_york(2024, 11, 2, 12, 0)).unwrap();
+        assert_eq!(first, utc(2024, 11, 3, 5, 30));
+        let second = next(&daily, first.with_timezone(&New_York)).unwrap();
+        assert_eq!(second, utc(2024, 11, 4, 6, 30));
+    }
+
+    #[test]
+    fn weekly_alarms_ring_on_their_days() {
+        let weekly = schedule("07:00", &["mon", "3"], None);
+        // Sunday.
+        assert_eq!(next(&weekly, new_york(2024, 6, 2, 12, 0)), Some(utc(2024, 6, 3, 11, 0)));
+        assert_eq!(next(&weekly, new_york(2024, 6, 3, 7, 0)), Some(utc(2024, 6, 5, 11, 0)));
+    }
+
+    #[test]
+    fn exceptions_pass_over_whole_ranges() {
+        let mut daily = schedule("07:00", &[], None);
+        daily.set_exceptions(vec![range("2024-06-03", "2024-06-05")]).unwrap();
+        assert_eq!(next(&daily, new_york(2024, 6, 2, 8, 0)), Some(utc(2024, 6, 6, 11, 0)));
+
+        // Excepted days don't use up the search window.
+        let mut weekly = schedule("07:00", &["mon"], None);
+        weekly.set_exceptions(vec![range("2024-06-01", "2024-06-30")]).unwrap();
+        assert_eq!(next(&weekly, new_york(2024, 5, 31, 8, 0)), Some(utc(2024, 7, 1, 11, 0)));
+
+        assert!(daily.set_exceptions(vec![range("2024-06-05", "2024-06-03")]).is_err());
+    }
+
+    #[test]
+    fn skipped_occurrence_does_not_ring() {
+        let mut daily = schedule("07:00", &[], None);
+        daily.skipped = Some(utc(2024, 6, 3, 11, 0));
+        assert_eq!(next(&daily, new_york(2024, 6, 2, 8, 0)), Some(utc(2024, 6, 4, 11, 0)));
+    }
+
+    #[test]
+    fn one_off_alarms_ring_once() {
+        let once = schedule("07:00", &[], Some("2024-06-10"));
+        assert_eq!(next(&once, new_york(2024, 6, 1, 0, 0)), Some(utc(2024, 6, 10, 11, 0)));
+        assert_eq!(next(&once, new_york(2024, 6, 10, 7, 0)), None);
+
+        let mut excepted = once.clone();
+        excepted.set_exceptions(vec![range("2024-06-09", "2024-06-11")]).unwrap();
+        assert_eq!(next(&excepted, new_york(2024, 6, 1, 0, 0)), None);
+
+        let in_gap = schedule("02:15", &[], Some("2024-03-10"));
+        assert_eq!(next(&in_gap, new_york(2024, 3, 9, 0, 0)), Some(utc(2024, 3, 10, 7, 0)));
+
+        assert!(AlarmSchedule::parse("07:00", &["mon".to_string()], Some("2024-06-10")).is_err());
+    }
+}
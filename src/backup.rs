@@ -1,11 +1,142 @@
 use std::fs;
-use std::path::PathBuf;
-use chrono::{DateTime, Local};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, Timelike, Utc};
 use anyhow::Result;
+use base64::{Engine as _, engine::general_purpose};
 use directories::ProjectDirs;
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, SqliteConnection, SqlitePool, sqlite::SqliteConnectOptions};
+use tokio::sync::{Mutex, MutexGuard, Notify, RwLock, broadcast};
+use crate::LOCKED_ERROR;
+use crate::database::Database;
+use crate::encryption::{Encryption, KdfParams};
+use crate::models::{
+    BackupDestination, BackupInfo, BackupProtection, BackupSettings, BackupStatus, BackupWarning,
+    RetentionPlan, RetentionPolicy,
+};
+
+pub const BACKUP_SETTINGS: &str = "backup_settings";
+
+const ARCHIVE_EXTENSION: &str = "pbak";
+/// Backups taken before archiving are plain SQLite copies.
+const LEGACY_EXTENSION: &str = "db";
+const ARCHIVE_MAGIC: &[u8; 8] = b"PABACKUP";
+const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+const ARCHIVE_FORMAT_VERSION: u32 = 1;
+
+/// Stored in the clear at the start of an archive, ahead of the sealed body:
+/// `magic || header length (u32 LE) || header JSON || nonce || ciphertext`.
+/// The body is the database compressed with deflate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ArchiveHeader {
+    format_version: u32,
+    app_version: String,
+    schema_version: Option<i64>,
+    created_at: DateTime<Utc>,
+    compression: String,
+    key: ArchiveKey,
+    /// The data key the diary rows inside were sealed with, itself sealed with
+    /// the backup passphrase key. Missing from data-key archives and from
+    /// archives written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data_key: Option<String>,
+    row_counts: BTreeMap<String, i64>,
+}
+
+impl ArchiveHeader {
+    fn new(
+        schema_version: Option<i64>,
+        row_counts: BTreeMap<String, i64>,
+        key: ArchiveKey,
+        data_key: Option<String>,
+    ) -> Self {
+        Self {
+            format_version: ARCHIVE_FORMAT_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version,
+            created_at: Utc::now(),
+            compression: "deflate".to_string(),
+            key,
+            data_key,
+            row_counts,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+enum ArchiveKey {
+    DataKey,
+    Passphrase(KdfParams),
+}
+
+/// Keys available for opening archives. The passphrase is only needed for
+/// archives sealed under a backup passphrase that is no longer the current one.
+pub struct ArchiveKeys<'a> {
+    pub data_key: &'a Encryption,
+    pub passphrase: Option<&'a str>,
+}
+
+/// A backup as a plain database file. Archives are extracted next to the
+/// backups and the extracted copy is removed on drop.
+struct Extracted {
+    path: PathBuf,
+    temporary: bool,
+    /// The data key the backup was taken under, when the archive tells.
+    data_key: Option<Encryption>,
+}
+
+impl Drop for Extracted {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A `VACUUM INTO` copy waiting to be archived as `backup_path`. The copy is
+/// removed on drop.
+struct RawSnapshot {
+    raw_path: PathBuf,
+    backup_path: PathBuf,
+}
+
+impl RawSnapshot {
+    fn partial_path(&self) -> PathBuf {
+        self.backup_path.with_extension("pbak.partial")
+    }
+}
+
+impl Drop for RawSnapshot {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.raw_path);
+    }
+}
+
+/// Persisted next to the backups rather than in the database, since writing
+/// it there would itself count as a change to back up.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct BackupState {
+    last_success_at: Option<DateTime<Utc>>,
+    last_backup_path: Option<String>,
+    last_fingerprint: Option<String>,
+}
 
 pub struct BackupManager {
     backup_dir: PathBuf,
+    /// Held for the duration of a backup, restore or key rotation so they never overlap.
+    busy: Mutex<()>,
+    status: std::sync::Mutex<BackupStatus>,
+    /// Wakes the scheduler when the backup settings change.
+    settings_changed: Notify,
+    warnings: broadcast::Sender<BackupWarning>,
 }
 
 impl BackupManager {
@@ -16,51 +147,932 @@ impl BackupManager {
         let backup_dir = proj_dirs.data_dir().join("backups");
         fs::create_dir_all(&backup_dir)?;
         
-        Ok(Self { backup_dir })
+        let state = Self::load_state(&backup_dir);
+        let status = BackupStatus {
+            last_success_at: state.last_success_at,
+            last_backup_path: state.last_backup_path,
+            ..Default::default()
+        };
+        
+        Ok(Self {
+            backup_dir,
+            busy: Mutex::new(()),
+            status: std::sync::Mutex::new(status),
+            settings_changed: Notify::new(),
+            warnings: broadcast::channel(16).0,
+        })
     }
-    
-    pub fn create_backup(&self, db_path: &PathBuf) -> Result<PathBuf> {
+
+    fn state_path(backup_dir: &Path) -> PathBuf {
+        backup_dir.join("backup_state.json")
+    }
+
+    fn load_state(backup_dir: &Path) -> BackupState {
+        fs::read(Self::state_path(backup_dir))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_state(&self, state: &BackupState) -> Result<()> {
+        fs::write(Self::state_path(&self.backup_dir), serde_json::to_vec_pretty(state)?)?;
+        Ok(())
+    }
+
+    pub fn status(&self) -> BackupStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        status.in_progress = self.busy.try_lock().is_err();
+        status.passphrase_enabled = Encryption::backup_passphrase_enabled().unwrap_or(false);
+        status
+    }
+
+    pub fn notify_settings_changed(&self) {
+        self.settings_changed.notify_one();
+    }
+
+    /// Warnings about destinations that were skipped or failed, as they happen.
+    pub fn subscribe_warnings(&self) -> broadcast::Receiver<BackupWarning> {
+        self.warnings.subscribe()
+    }
+
+    /// Backs up the database unless it is unchanged since the last successful
+    /// backup and `force` is false. Returns the new backup path, or `None` when
+    /// skipped. The outcome is recorded in [`BackupManager::status`]. Fails
+    /// while locked, since archives are sealed with the data key.
+    pub async fn backup_now(
+        &self,
+        db: &Mutex<Database>,
+        encryption: &RwLock<Option<Encryption>>,
+        force: bool,
+    ) -> Result<Option<PathBuf>> {
+        self.status.lock().unwrap().last_attempt_at = Some(Utc::now());
+
+        // A copy of the key, so locking the app does not wait for the backup.
+        let data_key = match encryption.read().await.as_ref() {
+            Some(encryption) => Encryption::new(encryption.key()),
+            None => Err(anyhow::anyhow!(LOCKED_ERROR)),
+        };
+        let result = match data_key {
+            Ok(data_key) => self.backup_if_changed(db, &data_key, force).await,
+            Err(e) => Err(e),
+        };
+        let mut status = self.status.lock().unwrap();
+        match &result {
+            Ok(Some(path)) => {
+                status.last_success_at = Some(Utc::now());
+                status.last_backup_path = Some(path.to_string_lossy().to_string());
+                status.last_error = None;
+            }
+            Ok(None) => status.last_error = None,
+            Err(e) => status.last_error = Some(e.to_string()),
+        }
+        result
+    }
+
+    /// The database is only locked while the snapshot is taken and checked.
+    /// Archiving it and copying it to each extra destination happen after the
+    /// lock is released; a destination that fails only produces a warning.
+    async fn backup_if_changed(&self, db: &Mutex<Database>, data_key: &Encryption, force: bool) -> Result<Option<PathBuf>> {
+        let _busy = self.try_begin_exclusive()
+            .ok_or_else(|| anyhow::anyhow!("Another backup or maintenance operation is in progress"))?;
+        let mut state = Self::load_state(&self.backup_dir);
+        let (fingerprint, settings, snapshot) = {
+            let db = db.lock().await;
+            let fingerprint = db_fingerprint(db.path())?;
+            if !force && state.last_fingerprint.as_deref() == Some(fingerprint.as_str()) {
+                return Ok(None);
+            }
+            let settings: BackupSettings = Database::get_setting(db.pool(), BACKUP_SETTINGS)
+                .await?
+                .unwrap_or_default();
+            (fingerprint, settings, self.take_snapshot(db.pool()).await?)
+        };
+
+        let path = Self::seal_snapshot(snapshot, data_key).await?;
+        apply_retention(&self.backup_dir, &settings.retention, true)?;
+        self.copy_to_destinations(&path, &settings.destinations).await;
+
+        state.last_success_at = Some(Utc::now());
+        state.last_backup_path = Some(path.to_string_lossy().to_string());
+        state.last_fingerprint = Some(fingerprint);
+        self.save_state(&state)?;
+        Ok(Some(path))
+    }
+
+    /// Claims exclusive access for a maintenance operation, or `None` if one is already running.
+    pub fn try_begin_exclusive(&self) -> Option<MutexGuard<'_, ()>> {
+        self.busy.try_lock().ok()
+    }
+
+    /// Copies a finished archive to each extra destination, off the async
+    /// workers since the drive may be slow.
+    async fn copy_to_destinations(&self, backup_path: &Path, destinations: &[BackupDestination]) {
+        let mut warnings = Vec::new();
+        for destination in destinations {
+            let (source, target) = (backup_path.to_path_buf(), destination.clone());
+            let result = tokio::task::spawn_blocking(move || copy_to_destination(&source, &target))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|copied| copied);
+            if let Err(e) = result {
+                let warning = BackupWarning {
+                    destination: destination.path.clone(),
+                    message: e.to_string(),
+                    at: Utc::now(),
+                };
+                // Nobody listening is fine; the warning is also kept in the status.
+                let _ = self.warnings.send(warning.clone());
+                warnings.push(warning);
+            }
+        }
+        self.status.lock().unwrap().destination_warnings = warnings;
+    }
+
+    /// Writes and verifies a backup archive; callers hold the busy lock.
+    async fn snapshot(&self, pool: &SqlitePool, data_key: &Encryption) -> Result<PathBuf> {
+        Self::seal_snapshot(self.take_snapshot(pool).await?, data_key).await
+    }
+
+    /// Snapshots the live database with `VACUUM INTO`, which SQLite runs inside a
+    /// read transaction, so the copy is consistent even while the pool is in use.
+    /// The copy is integrity checked before it is archived.
+    async fn take_snapshot(&self, pool: &SqlitePool) -> Result<RawSnapshot> {
         let timestamp = Local::now().format("%Y%m%d_%H%M%S");
-        let backup_name = format!("backup_{}.db", timestamp);
-        let backup_path = self.backup_dir.join(backup_name);
-        
-        fs::copy(db_path, &backup_path)?;
-        
-        // Clean up old backups (keep last 10)
-        self.cleanup_old_backups()?;
-        
-        Ok(backup_path)
+        let backup_path = self.backup_dir.join(format!("backup_{}.{}", timestamp, ARCHIVE_EXTENSION));
+        let snapshot = RawSnapshot { raw_path: backup_path.with_extension("db.partial"), backup_path };
+        for path in [&snapshot.raw_path, &snapshot.partial_path()] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        sqlx::query("VACUUM INTO ?")
+            .bind(snapshot.raw_path.to_string_lossy().to_string())
+            .execute(pool)
+            .await?;
+        Self::verify_backup_file(&snapshot.raw_path).await?;
+        Ok(snapshot)
     }
-    
-    fn cleanup_old_backups(&self) -> Result<()> {
-        let mut backups: Vec<(PathBuf, DateTime<Local>)> = Vec::new();
-        
-        for entry in fs::read_dir(&self.backup_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.extension().and_then(|s| s.to_str()) == Some("db") {
-                if let Ok(metadata) = entry.metadata() {
-                    if let Ok(created) = metadata.created() {
-                        let datetime: DateTime<Local> = created.into();
-                        backups.push((path, datetime));
+
+    /// Compresses and encrypts a snapshot. The archive only gets its final
+    /// name once it is completely written.
+    async fn seal_snapshot(snapshot: RawSnapshot, data_key: &Encryption) -> Result<PathBuf> {
+        let partial_path = snapshot.partial_path();
+        if let Err(e) = Self::archive_snapshot(&snapshot.raw_path, &partial_path, data_key).await {
+            let _ = fs::remove_file(&partial_path);
+            return Err(e);
+        }
+        fs::rename(&partial_path, &snapshot.backup_path)?;
+        Ok(snapshot.backup_path.clone())
+    }
+
+    async fn archive_snapshot(raw_path: &Path, archive_path: &Path, data_key: &Encryption) -> Result<()> {
+        let (schema_version, row_counts) = read_contents(raw_path).await?;
+
+        let (key, sealing_key, sealed_data_key) = match Encryption::load_backup_key(data_key)? {
+            Some((params, backup_key)) => {
+                let sealed_data_key = general_purpose::STANDARD.encode(backup_key.seal(data_key.key())?);
+                (ArchiveKey::Passphrase(params), backup_key, Some(sealed_data_key))
+            }
+            None => (ArchiveKey::DataKey, Encryption::new(data_key.key())?, None),
+        };
+        let header = ArchiveHeader::new(schema_version, row_counts, key, sealed_data_key);
+        let (raw_path, archive_path) = (raw_path.to_path_buf(), archive_path.to_path_buf());
+        tokio::task::spawn_blocking(move || write_archive(&raw_path, &archive_path, &header, &sealing_key)).await?
+    }
+
+    /// Resolves a backup id (its file stem) to a path inside the backup directory.
+    fn backup_path(&self, id: &str) -> Result<PathBuf> {
+        backup_path_in(&self.backup_dir, id)
+    }
+
+    /// All backups in the backup directory, newest first.
+    pub async fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        let ids = backup_ids_in(&self.backup_dir)?;
+        let mut backups = Vec::with_capacity(ids.len());
+        for (id, _) in ids {
+            backups.push(self.inspect_backup(&id).await?);
+        }
+        Ok(backups)
+    }
+
+    /// Describes a backup. Archives are described from their header alone, so
+    /// this needs no key.
+    pub async fn inspect_backup(&self, id: &str) -> Result<BackupInfo> {
+        let path = self.backup_path(id)?;
+        let size_bytes = fs::metadata(&path)?.len();
+
+        if !is_archive(&path) {
+            let (schema_version, row_counts) = read_contents(&path).await?;
+            return Ok(BackupInfo {
+                id: id.to_string(),
+                path: path.to_string_lossy().to_string(),
+                size_bytes,
+                created_at: parse_backup_timestamp(id)
+                    .and_then(|naive| naive.and_local_timezone(Local).earliest())
+                    .map(|t| t.with_timezone(&Utc)),
+                schema_version,
+                row_counts,
+                app_version: None,
+                protection: BackupProtection::None,
+            });
+        }
+
+        let header = read_archive_header(&mut fs::File::open(&path)?)?;
+        Ok(BackupInfo {
+            id: id.to_string(),
+            path: path.to_string_lossy().to_string(),
+            size_bytes,
+            created_at: Some(header.created_at),
+            schema_version: header.schema_version,
+            row_counts: header.row_counts,
+            app_version: Some(header.app_version),
+            protection: match header.key {
+                ArchiveKey::DataKey => BackupProtection::DataKey,
+                ArchiveKey::Passphrase(_) => BackupProtection::Passphrase,
+            },
+        })
+    }
+
+    /// Decrypts and decompresses the backup if needed, then checks its integrity.
+    pub async fn verify_backup(&self, id: &str, keys: &ArchiveKeys<'_>) -> Result<()> {
+        let extracted = self.extract(&self.backup_path(id)?, keys).await?;
+        Self::verify_backup_file(&extracted.path).await
+    }
+
+    /// Makes a plain database file out of a backup, extracting archives.
+    /// Data-key archives taken before a key rotation open with the retired key.
+    async fn extract(&self, path: &Path, keys: &ArchiveKeys<'_>) -> Result<Extracted> {
+        if !is_archive(path) {
+            return Ok(Extracted { path: path.to_path_buf(), temporary: false, data_key: None });
+        }
+
+        let (header, body) = read_archive(path)?;
+        let (compressed, data_key) = match &header.key {
+            ArchiveKey::DataKey => {
+                let mut candidates = vec![Encryption::new(keys.data_key.key())?];
+                candidates.extend(Encryption::load_retired_keys(keys.data_key)?.into_iter().rev());
+                let (compressed, key) = open_with_data_keys(&body, candidates)?;
+                (compressed, Some(key))
+            }
+            ArchiveKey::Passphrase(params) => {
+                let key = match Encryption::load_backup_key(keys.data_key)? {
+                    Some((current, key)) if current == *params => key,
+                    _ => {
+                        let passphrase = keys.passphrase
+                            .ok_or_else(|| anyhow::anyhow!("Backup passphrase is required to open this backup"))?
+                            .to_string();
+                        let params = params.clone();
+                        // Argon2 is deliberately slow; keep it off the async workers.
+                        tokio::task::spawn_blocking(move || params.derive(&passphrase)).await??
                     }
+                };
+                open_with_passphrase_key(&header, &body, &key)?
+            }
+        };
+
+        let extracted = Extracted { path: path.with_extension("db.extracted"), temporary: true, data_key };
+        inflate_to(&compressed, &extracted.path)?;
+        Ok(extracted)
+    }
+
+    /// Replaces the live database with backup `id` and reopens it in place.
+    /// Both archives and raw `.db` backups can be restored. A safety backup of
+    /// the current database is taken first and put back if the restored file
+    /// cannot be opened or was written under an unknown key. Diary rows sealed
+    /// under an earlier data key are re-encrypted with the current one. Returns
+    /// the id of the safety backup.
+    pub async fn restore_backup(
+        &self,
+        db: &mut Database,
+        id: &str,
+        keys: &ArchiveKeys<'_>,
+    ) -> Result<String> {
+        let _busy = self.try_begin_exclusive()
+            .ok_or_else(|| anyhow::anyhow!("Another backup or maintenance operation is in progress"))?;
+        let backup = self.extract(&self.backup_path(id)?, keys).await?;
+        Self::verify_backup_file(&backup.path).await?;
+
+        let safety_path = self.snapshot(db.pool(), keys.data_key).await?;
+        let db_path = db.path().clone();
+
+        db.close().await;
+        let restore_keys = Some((keys.data_key, backup.data_key.as_ref()));
+        let safety_id = safety_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if let Err(e) = Self::reopen_from(db, &backup.path, &db_path, restore_keys).await {
+            // Put the previous database back; surface the original error either way.
+            let rollback = async {
+                let safety = self.extract(&safety_path, keys).await?;
+                Self::reopen_from(db, &safety.path, &db_path, None).await
+            }.await;
+            if let Err(rollback_error) = rollback {
+                // Last resort, so commands don't run against the closed pool.
+                if let Ok(reopened) = Database::open(db_path.clone()).await {
+                    *db = reopened;
                 }
+                return Err(anyhow::anyhow!(
+                    "Restore failed ({}) and putting back the previous data failed too ({}); restart the app and restore safety backup {}",
+                    e, rollback_error, safety_id,
+                ));
             }
+            return Err(e);
         }
-        
-        // Sort by creation time, newest first
-        backups.sort_by(|a, b| b.1.cmp(&a.1));
-        
-        // Remove backups older than the 10 most recent
-        for (path, _) in backups.iter().skip(10) {
-            let _ = fs::remove_file(path);
+
+        Ok(safety_id)
+    }
+
+    /// `keys` is the current data key and, if known, the one the backup was taken under.
+    async fn reopen_from(
+        db: &mut Database,
+        source: &Path,
+        db_path: &Path,
+        keys: Option<(&Encryption, Option<&Encryption>)>,
+    ) -> Result<()> {
+        // Copy beside the live file first so the swap itself is a single rename.
+        let staging = db_path.with_extension("db.restore");
+        fs::copy(source, &staging)?;
+        for suffix in ["-wal", "-shm", "-journal"] {
+            let mut side = db_path.as_os_str().to_owned();
+            side.push(suffix);
+            let side = PathBuf::from(side);
+            if side.exists() {
+                fs::remove_file(side)?;
+            }
+        }
+        fs::rename(&staging, db_path)?;
+
+        // Re-runs migrations, so older backups are brought up to date.
+        *db = Database::open(db_path.to_path_buf()).await?;
+        if let Some((current, backup_key)) = keys {
+            // Newest keys first: raw `.db` backups do not say which key they used.
+            let mut candidates = Vec::new();
+            if let Some(key) = backup_key {
+                candidates.push(Encryption::new(key.key())?);
+            }
+            candidates.push(Encryption::new(current.key())?);
+            candidates.extend(Encryption::load_retired_keys(current)?.into_iter().rev());
+            let mut sealed_with = None;
+            for key in candidates {
+                if db.key_matches(&key).await? {
+                    sealed_with = Some(key);
+                    break;
+                }
+            }
+            let Some(sealed_with) = sealed_with else {
+                db.close().await;
+                return Err(anyhow::anyhow!("Backup was encrypted with a different key and cannot be restored"));
+            };
+            if sealed_with.key() == current.key() {
+                db.encrypt_plaintext_diary_entries(current).await?;
+            } else {
+                db.reencrypt_diary_entries(&sealed_with, current).await?;
+            }
         }
-        
         Ok(())
     }
     
+    /// Works out which backups `policy` keeps and which it would delete, without touching anything.
+    pub fn plan_retention(&self, policy: &RetentionPolicy) -> Result<RetentionPlan> {
+        plan_retention_in(&self.backup_dir, policy, true)
+    }
+    
+    /// Runs `PRAGMA integrity_check` against a backup file over its own read-only connection.
+    pub async fn verify_backup_file(path: &Path) -> Result<()> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .read_only(true);
+        let mut conn = SqliteConnection::connect_with(&options).await?;
+        let problems: Vec<(String,)> = sqlx::query_as("PRAGMA integrity_check")
+            .fetch_all(&mut conn)
+            .await?;
+        conn.close().await?;
+
+        match problems.as_slice() {
+            [(result,)] if result == "ok" => Ok(()),
+            _ => Err(anyhow::anyhow!(
+                "Backup failed integrity check: {}",
+                problems.into_iter().map(|(p,)| p).collect::<Vec<_>>().join("; ")
+            )),
+        }
+    }
+    
     pub fn get_backup_dir(&self) -> &PathBuf {
         &self.backup_dir
     }
-}
\ No newline at end of file
+}
+
+fn backup_path_in(dir: &Path, id: &str) -> Result<PathBuf> {
+    if parse_backup_timestamp(id).is_none() {
+        return Err(anyhow::anyhow!("Unknown backup: {}", id));
+    }
+    [ARCHIVE_EXTENSION, LEGACY_EXTENSION]
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", id, extension)))
+        .find(|path| path.exists())
+        .ok_or_else(|| anyhow::anyhow!("Unknown backup: {}", id))
+}
+
+/// Ids and timestamps of every backup in `dir`, newest first.
+fn backup_ids_in(dir: &Path) -> Result<Vec<(String, NaiveDateTime)>> {
+    Ok(backup_files_in(dir, true)?.into_iter().map(|(id, timestamp, _)| (id, timestamp)).collect())
+}
+
+/// Backups in `dir`, newest first. Only files named `backup_YYYYMMDD_HHMMSS`
+/// that really are backups count: archives must start with the archive magic,
+/// and raw `.db` backups, only looked for when `include_raw`, with SQLite's.
+/// Destinations are user-chosen folders, so nothing else there is touched.
+fn backup_files_in(dir: &Path, include_raw: bool) -> Result<Vec<(String, NaiveDateTime, PathBuf)>> {
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let magic: &[u8] = match path.extension().and_then(|s| s.to_str()) {
+            Some(ARCHIVE_EXTENSION) => ARCHIVE_MAGIC,
+            Some(LEGACY_EXTENSION) if include_raw => SQLITE_MAGIC,
+            _ => continue,
+        };
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+        let Some(timestamp) = parse_backup_timestamp(id) else { continue };
+        if starts_with_magic(&path, magic) {
+            backups.push((id.to_string(), timestamp, path.clone()));
+        }
+    }
+    backups.sort_by_key(|(_, timestamp, _)| Reverse(*timestamp));
+    Ok(backups)
+}
+
+fn starts_with_magic(path: &Path, magic: &[u8]) -> bool {
+    let mut start = vec![0u8; magic.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut start))
+        .is_ok_and(|_| start == magic)
+}
+
+fn plan_retention_in(dir: &Path, policy: &RetentionPolicy, include_raw: bool) -> Result<RetentionPlan> {
+    let backups: Vec<(String, NaiveDateTime)> = backup_files_in(dir, include_raw)?
+        .into_iter()
+        .map(|(id, timestamp, _)| (id, timestamp))
+        .collect();
+    let keep = select_retained(&backups, policy);
+    let (keep, delete) = backups.into_iter()
+        .map(|(id, _)| id)
+        .partition(|id| keep.contains(id));
+    Ok(RetentionPlan { keep, delete })
+}
+
+fn apply_retention(dir: &Path, policy: &RetentionPolicy, include_raw: bool) -> Result<()> {
+    let delete: HashSet<String> = plan_retention_in(dir, policy, include_raw)?.delete.into_iter().collect();
+    for (id, _, path) in backup_files_in(dir, include_raw)? {
+        if delete.contains(&id) {
+            let _ = fs::remove_file(path);
+        }
+    }
+    Ok(())
+}
+
+/// Copies a finished archive into `destination` and prunes it with the
+/// destination's own retention. The copy is checked against the original by
+/// checksum before it gets its final name. The destination folder itself is
+/// never created: if it is missing, the drive is most likely not mounted.
+fn copy_to_destination(backup_path: &Path, destination: &BackupDestination) -> Result<PathBuf> {
+    let dir = Path::new(&destination.path);
+    if !dir.is_dir() {
+        return Err(anyhow::anyhow!("Backup destination is not available: {}", destination.path));
+    }
+    let file_name = backup_path.file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid backup path"))?;
+    let target = dir.join(file_name);
+    let partial = target.with_extension("pbak.partial");
+
+    fs::copy(backup_path, &partial)?;
+    fs::File::open(&partial)?.sync_all()?;
+    if file_checksum(&partial)? != file_checksum(backup_path)? {
+        let _ = fs::remove_file(&partial);
+        return Err(anyhow::anyhow!("Copy to {} failed checksum verification", destination.path));
+    }
+    fs::rename(&partial, &target)?;
+
+    apply_retention(dir, &destination.retention, false)?;
+    Ok(target)
+}
+
+fn file_checksum(path: &Path) -> Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+fn is_archive(path: &Path) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some(ARCHIVE_EXTENSION)
+}
+
+/// Latest applied migration and per-table row counts of a plain database file.
+async fn read_contents(path: &Path) -> Result<(Option<i64>, BTreeMap<String, i64>)> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true);
+    let mut conn = SqliteConnection::connect_with(&options).await?;
+    let schema_version: Option<i64> = sqlx::query_scalar(
+        "SELECT MAX(version) FROM _sqlx_migrations WHERE success = TRUE")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap_or(None); // backups from before versioned migrations
+    let tables: Vec<(String,)> = sqlx::query_as(
+        "SELECT name FROM sqlite_master WHERE type = 'table'
+         AND name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations' ORDER BY name")
+        .fetch_all(&mut conn)
+        .await?;
+    let mut row_counts = BTreeMap::new();
+    for (table,) in tables {
+        let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM \"{}\"", table.replace('"', "\"\"")))
+            .fetch_one(&mut conn)
+            .await?;
+        row_counts.insert(table, count);
+    }
+    conn.close().await?;
+    Ok((schema_version, row_counts))
+}
+
+fn write_archive(raw_path: &Path, archive_path: &Path, header: &ArchiveHeader, key: &Encryption) -> Result<()> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    io::copy(&mut fs::File::open(raw_path)?, &mut encoder)?;
+    let body = key.seal(&encoder.finish()?)?;
+
+    let header = serde_json::to_vec(header)?;
+    let mut file = fs::File::create(archive_path)?;
+    file.write_all(ARCHIVE_MAGIC)?;
+    file.write_all(&u32::try_from(header.len())?.to_le_bytes())?;
+    file.write_all(&header)?;
+    file.write_all(&body)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// The header and the sealed body of the archive at `path`.
+fn read_archive(path: &Path) -> Result<(ArchiveHeader, Vec<u8>)> {
+    let mut file = fs::File::open(path)?;
+    let header = read_archive_header(&mut file)?;
+    if header.format_version > ARCHIVE_FORMAT_VERSION {
+        return Err(anyhow::anyhow!("Backup was written by a newer version of the app ({})", header.app_version));
+    }
+    let mut body = Vec::new();
+    file.read_to_end(&mut body)?;
+    Ok((header, body))
+}
+
+/// Opens a data-key archive body with the first of `candidates` that fits.
+fn open_with_data_keys(body: &[u8], candidates: Vec<Encryption>) -> Result<(Vec<u8>, Encryption)> {
+    candidates.into_iter()
+        .find_map(|key| key.open(body).ok().map(|compressed| (compressed, key)))
+        .ok_or_else(|| anyhow::anyhow!("Backup was encrypted with a different key and cannot be restored"))
+}
+
+/// Opens a passphrase archive body with the key derived from the passphrase,
+/// along with the data key the header records.
+fn open_with_passphrase_key(header: &ArchiveHeader, body: &[u8], key: &Encryption) -> Result<(Vec<u8>, Option<Encryption>)> {
+    let compressed = key.open(body).map_err(|_| anyhow::anyhow!("Incorrect backup passphrase"))?;
+    let data_key = header.data_key.as_deref()
+        .map(|sealed| Encryption::new(&key.open(&general_purpose::STANDARD.decode(sealed)?)?))
+        .transpose()?;
+    Ok((compressed, data_key))
+}
+
+fn inflate_to(compressed: &[u8], path: &Path) -> Result<()> {
+    io::copy(&mut DeflateDecoder::new(compressed), &mut fs::File::create(path)?)?;
+    Ok(())
+}
+
+/// Reads the header, leaving `reader` positioned at the start of the body.
+fn read_archive_header(reader: &mut impl Read) -> Result<ArchiveHeader> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != ARCHIVE_MAGIC {
+        return Err(anyhow::anyhow!("Not a backup archive"));
+    }
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let mut header = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut header)?;
+    Ok(serde_json::from_slice(&header)?)
+}
+
+/// Parses the timestamp out of a `backup_YYYYMMDD_HHMMSS` file stem.
+fn parse_backup_timestamp(id: &str) -> Option<NaiveDateTime> {
+    let stamp = id.strip_prefix("backup_")?;
+    NaiveDateTime::parse_from_str(stamp, "%Y%m%d_%H%M%S").ok()
+}
+
+/// Maps a timestamp to its retention bucket; the last field tells tiers apart.
+type BucketFn = fn(&NaiveDateTime) -> (i32, u32, u32, u32);
+
+/// Grandfather-father-son selection: walking from newest to oldest, the first
+/// backup seen in each hour, day, ISO week and month is kept until that tier
+/// has its quota. The newest backup is always kept.
+fn select_retained(backups: &[(String, NaiveDateTime)], policy: &RetentionPolicy) -> HashSet<String> {
+    let mut keep = HashSet::new();
+    if let Some((newest, _)) = backups.first() {
+        keep.insert(newest.clone());
+    }
+
+    let tiers: [(u32, BucketFn); 4] = [
+        (policy.hourly, |t| (t.year(), t.ordinal(), t.hour(), 0)),
+        (policy.daily, |t| (t.year(), t.ordinal(), 0, 0)),
+        (policy.weekly, |t| (t.iso_week().year(), t.iso_week().week(), 0, 1)),
+        (policy.monthly, |t| (t.year(), t.month(), 0, 2)),
+    ];
+    for (quota, bucket_of) in tiers {
+        let mut buckets = HashSet::new();
+        for (id, timestamp) in backups {
+            if buckets.len() >= quota as usize {
+                break;
+            }
+            if buckets.insert(bucket_of(timestamp)) {
+                keep.insert(id.clone());
+            }
+        }
+    }
+    keep
+}
+
+/// Size and modification time of the database and its WAL, used to tell
+/// whether anything was written since the last backup.
+fn db_fingerprint(db_path: &Path) -> Result<String> {
+    let mut parts = Vec::new();
+    let mut wal = db_path.as_os_str().to_owned();
+    wal.push("-wal");
+    for path in [db_path.to_path_buf(), PathBuf::from(wal)] {
+        if let Ok(metadata) = fs::metadata(&path) {
+            let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+            parts.push(format!("{}:{}", metadata.len(), modified));
+        }
+    }
+    Ok(parts.join(";"))
+}
+
+/// Runs for the lifetime of the app, backing up on the configured interval.
+pub async fn run_schedule(
+    manager: Arc<BackupManager>,
+    db: Arc<Mutex<Database>>,
+    encryption: Arc<RwLock<Option<Encryption>>>,
+) {
+    loop {
+        let settings: BackupSettings = {
+            let db = db.lock().await;
+            Database::get_setting(db.pool(), BACKUP_SETTINGS).await
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+        };
+
+        let interval = chrono::Duration::minutes(i64::from(settings.interval_minutes.max(1)));
+        let status = manager.status();
+        let last_run = status.last_attempt_at.max(status.last_success_at);
+        let due = last_run.map(|t| t + interval).unwrap_or_else(Utc::now);
+        manager.status.lock().unwrap().next_due_at = settings.enabled.then_some(due);
+
+        let wait = (due - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait), if settings.enabled => {
+                // Failures are recorded in the status for the UI to show.
+                let _ = manager.backup_now(&db, &encryption, false).await;
+            }
+            _ = manager.settings_changed.notified() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(hourly: u32, daily: u32, weekly: u32, monthly: u32) -> RetentionPolicy {
+        RetentionPolicy { hourly, daily, weekly, monthly }
+    }
+
+    /// Backups taken at `stamps` (`YYYY-MM-DD HH:MM:SS`), newest first as
+    /// `backup_ids_in` returns them.
+    fn backups(stamps: &[&str]) -> Vec<(String, NaiveDateTime)> {
+        let mut backups: Vec<(String, NaiveDateTime)> = stamps.iter()
+            .map(|stamp| {
+                let at = NaiveDateTime::parse_from_str(stamp, "%Y-%m-%d %H:%M:%S").unwrap();
+                (format!("backup_{}", at.format("%Y%m%d_%H%M%S")), at)
+            })
+            .collect();
+        backups.sort_by_key(|(_, at)| Reverse(*at));
+        backups
+    }
+
+    fn kept(stamps: &[&str], policy: &RetentionPolicy) -> Vec<String> {
+        let backups = backups(stamps);
+        let keep = select_retained(&backups, policy);
+        backups.into_iter()
+            .filter(|(id, _)| keep.contains(id))
+            .map(|(_, at)| at.format("%Y-%m-%d %H:%M:%S").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn zero_quotas_keep_only_the_newest() {
+        let stamps = ["2024-03-01 10:00:00", "2024-03-01 09:00:00", "2024-02-01 09:00:00"];
+        assert_eq!(kept(&stamps, &policy(0, 0, 0, 0)), vec!["2024-03-01 10:00:00"]);
+        assert!(select_retained(&[], &policy(0, 0, 0, 0)).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_newest_backup_of_each_hour() {
+        let stamps = [
+            "2024-03-01 10:40:00", "2024-03-01 10:20:00", "2024-03-01 10:00:00",
+            "2024-03-01 09:59:59", "2024-03-01 09:00:00",
+            "2024-03-01 08:30:00",
+        ];
+        assert_eq!(
+            kept(&stamps, &policy(2, 0, 0, 0)),
+            vec!["2024-03-01 10:40:00", "2024-03-01 09:59:59"],
+        );
+    }
+
+    #[test]
+    fn days_split_at_midnight() {
+        let stamps = ["2024-03-02 00:01:00", "2024-03-01 23:59:00", "2024-03-01 12:00:00", "2024-02-29 23:00:00"];
+        assert_eq!(
+            kept(&stamps, &policy(0, 2, 0, 0)),
+            vec!["2024-03-02 00:01:00", "2024-03-01 23:59:00"],
+        );
+    }
+
+    #[test]
+    fn weeks_follow_iso_weeks_across_the_new_year() {
+        // Monday 2024-12-30 already belongs to ISO week 1 of 2025; Sunday 2024-12-29 does not.
+        let stamps = ["2025-01-02 12:00:00", "2024-12-30 12:00:00", "2024-12-29 12:00:00", "2024-12-22 12:00:00"];
+        assert_eq!(
+            kept(&stamps, &policy(0, 0, 2, 0)),
+            vec!["2025-01-02 12:00:00", "2024-12-29 12:00:00"],
+        );
+    }
+
+    #[test]
+    fn months_split_on_the_first() {
+        let stamps = ["2024-03-01 00:00:00", "2024-02-29 23:59:59", "2024-02-01 00:00:00", "2024-01-31 12:00:00"];
+        assert_eq!(
+            kept(&stamps, &policy(0, 0, 0, 2)),
+            vec!["2024-03-01 00:00:00", "2024-02-29 23:59:59"],
+        );
+    }
+
+    #[test]
+    fn quotas_count_periods_that_have_backups() {
+        // A gap of several days does not use up the daily quota.
+        let stamps = ["2024-03-10 12:00:00", "2024-03-03 12:00:00", "2024-03-01 12:00:00", "2024-02-20 12:00:00"];
+        assert_eq!(
+            kept(&stamps, &policy(0, 3, 0, 0)),
+            vec!["2024-03-10 12:00:00", "2024-03-03 12:00:00", "2024-03-01 12:00:00"],
+        );
+    }
+
+    #[test]
+    fn overlapping_tiers_keep_the_union() {
+        let stamps = [
+            "2024-03-04 10:30:00", "2024-03-04 09:30:00", "2024-03-03 18:00:00", "2024-03-02 18:00:00",
+            "2024-02-27 18:00:00", "2024-01-15 18:00:00", "2023-12-15 18:00:00",
+        ];
+        // The newest backup fills a slot in every tier at once; each tier then
+        // reaches back on its own.
+        assert_eq!(
+            kept(&stamps, &policy(2, 2, 2, 3)),
+            vec![
+                "2024-03-04 10:30:00", // hourly, daily, weekly, monthly
+                "2024-03-04 09:30:00", // hourly
+                "2024-03-03 18:00:00", // daily, weekly (ISO week 9)
+                "2024-02-27 18:00:00", // monthly
+                "2024-01-15 18:00:00", // monthly
+            ],
+        );
+    }
+
+    #[test]
+    fn destination_retention_only_deletes_archives() {
+        let dir = std::env::temp_dir().join(format!("productivity-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = |name: &str| {
+            let mut contents = ARCHIVE_MAGIC.to_vec();
+            contents.extend_from_slice(b"body");
+            fs::write(dir.join(name), contents).unwrap();
+        };
+        archive("backup_20240301_100000.pbak");
+        archive("backup_20240201_100000.pbak");
+        // Someone else's files that happen to look like backups.
+        fs::write(dir.join("backup_20240101_100000.pbak"), b"not an archive").unwrap();
+        fs::write(dir.join("backup_20231201_100000.db"), SQLITE_MAGIC).unwrap();
+        fs::write(dir.join("backup_notes.pbak"), ARCHIVE_MAGIC).unwrap();
+
+        apply_retention(&dir, &policy(0, 0, 0, 0), false).unwrap();
+        let mut left: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, vec![
+            "backup_20231201_100000.db",
+            "backup_20240101_100000.pbak",
+            "backup_20240301_100000.pbak",
+            "backup_notes.pbak",
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn input_order_decides_which_backup_represents_a_period() {
+        // Callers pass backups newest first; the first one seen in a period wins.
+        let newest_first = backups(&["2024-03-01 10:40:00", "2024-03-01 10:10:00"]);
+        let keep = select_retained(&newest_first, &policy(1, 0, 0, 0));
+        assert_eq!(keep, HashSet::from(["backup_20240301_104000".to_string()]));
+    }
+
+    /// A snapshot file and the archive path next to it, in a fresh directory.
+    fn snapshot() -> (PathBuf, PathBuf, Vec<u8>) {
+        let dir = std::env::temp_dir().join(format!("productivity-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut contents = SQLITE_MAGIC.to_vec();
+        contents.extend(std::iter::repeat(b"diary entry ").take(200).flatten());
+        fs::write(dir.join("snapshot.db"), &contents).unwrap();
+        (dir.join("snapshot.db"), dir.join("backup_20240301_100000.pbak"), contents)
+    }
+
+    /// Cheap Argon2id parameters so the tests don't spend seconds deriving.
+    fn quick_params() -> KdfParams {
+        KdfParams {
+            kdf: "argon2id".to_string(),
+            salt: general_purpose::STANDARD.encode("backup-salt"),
+            m_cost: 1024,
+            t_cost: 1,
+            p_cost: 1,
+        }
+    }
+
+    fn inflated(compressed: &[u8], raw_path: &Path) -> Vec<u8> {
+        let path = raw_path.with_extension("db.extracted");
+        inflate_to(compressed, &path).unwrap();
+        fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn archives_open_with_the_data_key_or_a_retired_one() {
+        let (raw_path, archive_path, contents) = snapshot();
+        let data_key = Encryption::new(&Encryption::generate_key()).unwrap();
+        let header = ArchiveHeader::new(Some(16), BTreeMap::from([("todos".to_string(), 3)]), ArchiveKey::DataKey, None);
+        write_archive(&raw_path, &archive_path, &header, &data_key).unwrap();
+        assert!(is_archive(&archive_path));
+
+        let (header, body) = read_archive(&archive_path).unwrap();
+        assert!(matches!(header.key, ArchiveKey::DataKey));
+        assert_eq!(header.schema_version, Some(16));
+        assert_eq!(header.row_counts.get("todos"), Some(&3));
+        let rotated = Encryption::new(&Encryption::generate_key()).unwrap();
+        let retired = Encryption::new(data_key.key()).unwrap();
+        let (compressed, key) = open_with_data_keys(&body, vec![rotated, retired]).unwrap();
+        assert_eq!(key.key(), data_key.key());
+        assert_eq!(inflated(&compressed, &raw_path), contents);
+
+        let other = Encryption::new(&Encryption::generate_key()).unwrap();
+        let Err(error) = open_with_data_keys(&body, vec![other]) else { panic!("opened with the wrong key") };
+        assert_eq!(error.to_string(), "Backup was encrypted with a different key and cannot be restored");
+        fs::remove_dir_all(raw_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn passphrase_archives_open_with_the_passphrase_alone() {
+        let (raw_path, archive_path, contents) = snapshot();
+        let data_key = Encryption::new(&Encryption::generate_key()).unwrap();
+        let params = quick_params();
+        let backup_key = params.derive("correct horse").unwrap();
+        let sealed = general_purpose::STANDARD.encode(backup_key.seal(data_key.key()).unwrap());
+        let header = ArchiveHeader::new(Some(16), BTreeMap::new(), ArchiveKey::Passphrase(params), Some(sealed));
+        write_archive(&raw_path, &archive_path, &header, &backup_key).unwrap();
+
+        let (header, body) = read_archive(&archive_path).unwrap();
+        let ArchiveKey::Passphrase(params) = &header.key else { panic!("expected a passphrase archive") };
+        let key = params.derive("correct horse").unwrap();
+        let (compressed, recovered) = open_with_passphrase_key(&header, &body, &key).unwrap();
+        assert_eq!(recovered.unwrap().key(), data_key.key());
+        assert_eq!(inflated(&compressed, &raw_path), contents);
+
+        let wrong = params.derive("battery staple").unwrap();
+        let Err(error) = open_with_passphrase_key(&header, &body, &wrong) else { panic!("opened with the wrong passphrase") };
+        assert_eq!(error.to_string(), "Incorrect backup passphrase");
+        // The data key alone does not open a passphrase archive.
+        assert!(open_with_data_keys(&body, vec![data_key]).is_err());
+        fs::remove_dir_all(raw_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn archives_from_a_newer_format_are_refused() {
+        let (raw_path, archive_path, _) = snapshot();
+        let key = Encryption::new(&Encryption::generate_key()).unwrap();
+        let mut header = ArchiveHeader::new(None, BTreeMap::new(), ArchiveKey::DataKey, None);
+        header.format_version = ARCHIVE_FORMAT_VERSION + 1;
+        write_archive(&raw_path, &archive_path, &header, &key).unwrap();
+        assert!(read_archive(&archive_path).unwrap_err().to_string().starts_with("Backup was written by a newer version"));
+        fs::remove_dir_all(raw_path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,432 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use anyhow::{bail, Result};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::Notify;
+use crate::models::{Lap, Stopwatch, StopwatchSnapshot, Timer, TimerSnapshot, TimerStatus};
+use crate::notifications::{NotificationKind, NotificationService};
+
+/// Emitted every second while any timer runs, with the running [`TimerSnapshot`]s.
+pub const TIMER_TICK_EVENT: &str = "timer-tick";
+
+/// Emitted with a [`TimerSnapshot`] when a timer reaches zero.
+pub const TIMER_COMPLETED_EVENT: &str = "timer-completed";
+
+/// Emitted every second while the stopwatch runs, with a [`StopwatchSnapshot`].
+pub const STOPWATCH_TICK_EVENT: &str = "stopwatch-tick";
+
+const TICK: Duration = Duration::from_secs(1);
+
+pub const MAX_TIMER_SECONDS: u64 = 100 * 60 * 60;
+
+/// Everything the clock keeps across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ClockState {
+    next_id: u64,
+    timers: Vec<Timer>,
+    stopwatch: Stopwatch,
+}
+
+/// Owns the countdown timers and the stopwatch, so they keep running across
+/// webview reloads and app restarts.
+pub struct ClockManager {
+    state: Mutex<ClockState>,
+    state_path: PathBuf,
+    changed: Notify,
+}
+
+fn running_ms(running_since: Option<DateTime<Utc>>, now: DateTime<Utc>) -> i64 {
+    // A clock set back while running counts as no time passing.
+    running_since.map_or(0, |since| (now - since).num_milliseconds().max(0))
+}
+
+impl Timer {
+    fn remaining_ms(&self, now: DateTime<Utc>) -> i64 {
+        (self.duration_ms - self.accumulated_ms - running_ms(self.running_since, now)).max(0)
+    }
+
+    fn snapshot(&self, now: DateTime<Utc>) -> TimerSnapshot {
+        let status = if self.completed_at.is_some() {
+            TimerStatus::Completed
+        } else if self.running_since.is_some() {
+            TimerStatus::Running
+        } else {
+            TimerStatus::Paused
+        };
+        TimerSnapshot { timer: self.clone(), status, remaining_ms: self.remaining_ms(now) }
+    }
+}
+
+impl Stopwatch {
+    fn elapsed_ms(&self, now: DateTime<Utc>) -> i64 {
+        self.accumulated_ms + running_ms(self.running_since, now)
+    }
+
+    fn snapshot(&self, now: DateTime<Utc>) -> StopwatchSnapshot {
+        StopwatchSnapshot {
+            stopwatch: self.clone(),
+            running: self.running_since.is_some(),
+            elapsed_ms: self.elapsed_ms(now),
+        }
+    }
+}
+
+impl ClockState {
+    fn timer_mut(&mut self, id: u64) -> Result<&mut Timer> {
+        match self.timers.iter_mut().find(|timer| timer.id == id) {
+            Some(timer) => Ok(timer),
+            None => bail!("Timer {} not found", id),
+        }
+    }
+}
+
+impl ClockManager {
+    pub fn new() -> Result<Self> {
+        let proj_dirs = ProjectDirs::from("com", "productivityapp", "app")
+            .ok_or_else(|| anyhow::anyhow!("Failed to get project directories"))?;
+        fs::create_dir_all(proj_dirs.data_dir())?;
+        Ok(Self::load(proj_dirs.data_dir().join("clock_state.json")))
+    }
+
+    fn load(state_path: PathBuf) -> Self {
+        // A missing or unreadable file starts the clock empty rather than
+        // keeping the app from starting.
+        let state = fs::read(&state_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        Self {
+            state: Mutex::new(state),
+            state_path,
+            changed: Notify::new(),
+        }
+    }
+
+    // Write-then-rename so a crash never leaves a half-written file behind.
+    fn save(&self, state: &ClockState) -> Result<()> {
+        let tmp_path = self.state_path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(state)?)?;
+        fs::rename(&tmp_path, &self.state_path)?;
+        Ok(())
+    }
+
+    /// Applies a change, persists it and wakes the ticker.
+    fn update<T>(&self, change: impl FnOnce(&mut ClockState, DateTime<Utc>) -> Result<T>) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        let result = change(&mut state, Utc::now())?;
+        self.save(&state)?;
+        self.changed.notify_one();
+        Ok(result)
+    }
+
+    pub fn timers(&self) -> Vec<TimerSnapshot> {
+        let now = Utc::now();
+        self.state.lock().unwrap().timers.iter().map(|timer| timer.snapshot(now)).collect()
+    }
+
+    pub fn create_timer(&self, label: Option<String>, seconds: u64, start: bool) -> Result<TimerSnapshot> {
+        if seconds == 0 || seconds > MAX_TIMER_SECONDS {
+            bail!("Timer length must be between 1 and {} seconds, not {}", MAX_TIMER_SECONDS, seconds);
+        }
+        self.update(|state, now| {
+            state.next_id += 1;
+            let timer = Timer {
+                id: state.next_id,
+                label,
+                duration_ms: seconds as i64 * 1000,
+                accumulated_ms: 0,
+                running_since: start.then_some(now),
+                completed_at: None,
+                created_at: now,
+            };
+            state.timers.push(timer.clone());
+            Ok(timer.snapshot(now))
+        })
+    }
+
+    /// Starts or resumes a timer. A completed timer starts over.
+    pub fn start_timer(&self, id: u64) -> Result<TimerSnapshot> {
+        self.update(|state, now| {
+            let timer = state.timer_mut(id)?;
+            if timer.completed_at.take().is_some() {
+                timer.accumulated_ms = 0;
+            }
+            timer.running_since.get_or_insert(now);
+            Ok(timer.snapshot(now))
+        })
+    }
+
+    pub fn pause_timer(&self, id: u64) -> Result<TimerSnapshot> {
+        self.update(|state, now| {
+            let timer = state.timer_mut(id)?;
+            timer.accumulated_ms += running_ms(timer.running_since.take(), now);
+            Ok(timer.snapshot(now))
+        })
+    }
+
+    /// Stops a timer and winds it back to its full length.
+    pub fn reset_timer(&self, id: u64) -> Result<TimerSnapshot> {
+        self.update(|state, now| {
+            let timer = state.timer_mut(id)?;
+            timer.accumulated_ms = 0;
+            timer.running_since = None;
+            timer.completed_at = None;
+            Ok(timer.snapshot(now))
+        })
+    }
+
+    pub fn delete_timer(&self, id: u64) -> Result<()> {
+        self.update(|state, _| {
+            let before = state.timers.len();
+            state.timers.retain(|timer| timer.id != id);
+            if state.timers.len() == before {
+                bail!("Timer {} not found", id);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn stopwatch(&self) -> StopwatchSnapshot {
+        self.state.lock().unwrap().stopwatch.snapshot(Utc::now())
+    }
+
+    pub fn start_stopwatch(&self) -> Result<StopwatchSnapshot> {
+        self.update(|state, now| {
+            state.stopwatch.running_since.get_or_insert(now);
+            Ok(state.stopwatch.snapshot(now))
+        })
+    }
+
+    pub fn pause_stopwatch(&self) -> Result<StopwatchSnapshot> {
+        self.update(|state, now| {
+            let stopwatch = &mut state.stopwatch;
+            stopwatch.accumulated_ms += running_ms(stopwatch.running_since.take(), now);
+            Ok(stopwatch.snapshot(now))
+        })
+    }
+
+    /// Stops the stopwatch and clears its laps.
+    pub fn reset_stopwatch(&self) -> Result<StopwatchSnapshot> {
+        self.update(|state, now| {
+            state.stopwatch = Stopwatch::default();
+            Ok(state.stopwatch.snapshot(now))
+        })
+    }
+
+    pub fn lap_stopwatch(&self) -> Result<Lap> {
+        self.update(|state, now| {
+            let stopwatch = &mut state.stopwatch;
+            if stopwatch.running_since.is_none() {
+                bail!("Stopwatch is not running");
+            }
+            let total_ms = stopwatch.elapsed_ms(now);
+            let previous_ms = stopwatch.laps.last().map_or(0, |lap| lap.total_ms);
+            let lap = Lap {
+                number: stopwatch.laps.len() as u32 + 1,
+                lap_ms: total_ms - previous_ms,
+                total_ms,
+            };
+            stopwatch.laps.push(lap.clone());
+            Ok(lap)
+        })
+    }
+
+    /// Marks running timers that reached zero as completed.
+    fn complete_due(&self, now: DateTime<Utc>) -> Vec<TimerSnapshot> {
+        let mut state = self.state.lock().unwrap();
+        let mut completed = Vec::new();
+        for timer in state.timers.iter_mut() {
+            if timer.running_since.is_some() && timer.remaining_ms(now) == 0 {
+                timer.accumulated_ms = timer.duration_ms;
+                timer.running_since = None;
+                timer.completed_at = Some(now);
+                completed.push(timer.snapshot(now));
+            }
+        }
+        if !completed.is_empty() {
+            let _ = self.save(&state);
+        }
+        completed
+    }
+}
+
+/// Emits ticks while anything runs and completes timers that reach zero,
+/// including ones that ran out while the app was closed.
+pub async fn run_ticker(clock: Arc<ClockManager>, notifications: Arc<NotificationService>, app: AppHandle) {
+    loop {
+        let now = Utc::now();
+        for snapshot in clock.complete_due(now) {
+            let title = snapshot.timer.label.clone()
+                .filter(|label| !label.is_empty())
+                .unwrap_or_else(|| "Timer".to_string());
+            let _ = notifications.notify(NotificationKind::Timer, title, "Time's up");
+            let _ = app.emit(TIMER_COMPLETED_EVENT, snapshot);
+        }
+
+        let (running, stopwatch, next_completion) = {
+            let state = clock.state.lock().unwrap();
+            let running: Vec<TimerSnapshot> = state.timers.iter()
+                .filter(|timer| timer.running_since.is_some())
+                .map(|timer| timer.snapshot(now))
+                .collect();
+            let next_completion = running.iter().map(|snapshot| snapshot.remaining_ms).min();
+            let stopwatch = state.stopwatch.running_since.map(|_| state.stopwatch.snapshot(now));
+            (running, stopwatch, next_completion)
+        };
+        if !running.is_empty() {
+            let _ = app.emit(TIMER_TICK_EVENT, running);
+        }
+        let stopwatch_running = stopwatch.is_some();
+        if let Some(stopwatch) = stopwatch {
+            let _ = app.emit(STOPWATCH_TICK_EVENT, stopwatch);
+        }
+
+        if next_completion.is_none() && !stopwatch_running {
+            clock.changed.notified().await;
+            continue;
+        }
+        // Wake on the tick, or sooner when a timer runs out in between.
+        let wait = next_completion
+            .map(|ms| Duration::from_millis(ms as u64))
+            .unwrap_or(TICK)
+            .min(TICK);
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = clock.changed.notified() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration as TimeDelta;
+
+    fn state_path() -> PathBuf {
+        std::env::temp_dir().join(format!("clock-test-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    /// Moves every running clock `seconds` into the past and saves, as if
+    /// that much time went by.
+    fn wait(clock: &ClockManager, seconds: i64) {
+        let mut state = clock.state.lock().unwrap();
+        let shift = |since: &mut Option<DateTime<Utc>>| {
+            if let Some(since) = since {
+                *since -= TimeDelta::seconds(seconds);
+            }
+        };
+        for timer in state.timers.iter_mut() {
+            shift(&mut timer.running_since);
+        }
+        shift(&mut state.stopwatch.running_since);
+        clock.save(&state).unwrap();
+    }
+
+    /// `ms` give or take the time the test itself takes.
+    fn assert_about(actual: i64, ms: i64) {
+        assert!((actual - ms).abs() < 500, "expected about {} ms, got {}", ms, actual);
+    }
+
+    #[test]
+    fn timers_count_down_only_while_running() {
+        let clock = ClockManager::load(state_path());
+        let id = clock.create_timer(Some("Tea".to_string()), 60, true).unwrap().timer.id;
+        wait(&clock, 10);
+
+        let paused = clock.pause_timer(id).unwrap();
+        assert_eq!(paused.status, TimerStatus::Paused);
+        assert_about(paused.remaining_ms, 50_000);
+        wait(&clock, 600);
+        assert_eq!(clock.timers()[0].remaining_ms, paused.remaining_ms);
+
+        let resumed = clock.start_timer(id).unwrap();
+        assert_eq!(resumed.status, TimerStatus::Running);
+        wait(&clock, 20);
+        assert_about(clock.timers()[0].remaining_ms, 30_000);
+
+        let reset = clock.reset_timer(id).unwrap();
+        assert_eq!((reset.status, reset.remaining_ms), (TimerStatus::Paused, 60_000));
+        assert!(clock.create_timer(None, 0, false).is_err());
+        assert!(clock.create_timer(None, MAX_TIMER_SECONDS + 1, false).is_err());
+    }
+
+    #[test]
+    fn timers_complete_once_at_zero() {
+        let clock = ClockManager::load(state_path());
+        let id = clock.create_timer(None, 5, true).unwrap().timer.id;
+        clock.create_timer(None, 5, false).unwrap();
+        wait(&clock, 6);
+
+        let now = Utc::now();
+        let completed = clock.complete_due(now);
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].timer.id, id);
+        assert_eq!(completed[0].status, TimerStatus::Completed);
+        assert_eq!(completed[0].remaining_ms, 0);
+        assert_eq!(completed[0].timer.completed_at, Some(now));
+        assert!(clock.complete_due(Utc::now()).is_empty());
+
+        // Starting a completed timer starts it over.
+        let restarted = clock.start_timer(id).unwrap();
+        assert_eq!(restarted.status, TimerStatus::Running);
+        assert_about(restarted.remaining_ms, 5_000);
+    }
+
+    #[test]
+    fn laps_measure_since_the_previous_lap() {
+        let clock = ClockManager::load(state_path());
+        assert!(clock.lap_stopwatch().is_err());
+        clock.start_stopwatch().unwrap();
+        wait(&clock, 3);
+        let first = clock.lap_stopwatch().unwrap();
+        wait(&clock, 2);
+        let second = clock.lap_stopwatch().unwrap();
+
+        assert_eq!((first.number, second.number), (1, 2));
+        assert_eq!(first.lap_ms, first.total_ms);
+        assert_about(first.total_ms, 3_000);
+        assert_eq!(second.lap_ms, second.total_ms - first.total_ms);
+        assert_about(second.total_ms, 5_000);
+        assert_about(second.lap_ms, 2_000);
+
+        clock.pause_stopwatch().unwrap();
+        assert!(clock.lap_stopwatch().is_err());
+        assert_eq!(clock.stopwatch().stopwatch.laps.len(), 2);
+        let reset = clock.reset_stopwatch().unwrap();
+        assert!(reset.stopwatch.laps.is_empty());
+        assert_eq!(reset.elapsed_ms, 0);
+    }
+
+    #[test]
+    fn running_timers_carry_on_after_a_restart() {
+        let path = state_path();
+        let clock = ClockManager::load(path.clone());
+        let running = clock.create_timer(None, 120, true).unwrap().timer.id;
+        let ran_out = clock.create_timer(None, 60, true).unwrap().timer.id;
+        let paused = clock.create_timer(None, 30, false).unwrap().timer.id;
+        clock.start_stopwatch().unwrap();
+        wait(&clock, 90);
+        drop(clock);
+
+        let clock = ClockManager::load(path);
+        let timers = clock.timers();
+        let timer = |id: u64| timers.iter().find(|snapshot| snapshot.timer.id == id).unwrap();
+        assert_eq!(timer(running).status, TimerStatus::Running);
+        assert_about(timer(running).remaining_ms, 30_000);
+        assert_eq!(timer(paused).remaining_ms, 30_000);
+        assert!(clock.stopwatch().running);
+        assert_about(clock.stopwatch().elapsed_ms, 90_000);
+
+        // The ticker completes a timer that ran out while the app was closed.
+        let completed = clock.complete_due(Utc::now());
+        assert_eq!(completed.iter().map(|snapshot| snapshot.timer.id).collect::<Vec<_>>(), [ran_out]);
+        assert_eq!(clock.create_timer(None, 1, false).unwrap().timer.id, paused + 1);
+    }
+}
@@ -1,10 +1,20 @@
-use crate::{AppState, models::*};
+use crate::{AppState, LOCKED_ERROR, models::*, encryption::Encryption};
+use crate::database::{Database, DiaryMergeReport, DIARY_MODE_SETTING};
+use crate::backup::{ArchiveKeys, BACKUP_SETTINGS};
+use crate::notifications::DO_NOT_DISTURB_SETTING;
+use crate::alarms::{upcoming_occurrences, validate_snooze_minutes, AlarmError, ALARM_SNOOZE_DEFAULTS};
+use crate::focus::{self, FocusError};
+use crate::pomodoro::{self, POMODORO_SETTINGS};
+use crate::world_clock::{
+    parse_time_zone, parse_work_time, plan_meeting as plan_meeting_windows, time_zone_names, Participant, Zone,
+    DEFAULT_WORK_END, DEFAULT_WORK_START,
+};
 use tauri::State;
 use sqlx::QueryBuilder;
 use std::fs;
 use std::path::Path;
 use base64::Engine;
-use chrono::{Utc, NaiveDate};
+use chrono::{DateTime, Local, Utc, NaiveDate};
 use anyhow::Result;
 
 #[tauri::command]
@@ -13,10 +23,11 @@ pub async fn get_focus_sessions(
     start_date: Option<String>,
     end_date: Option<String>,
 ) -> Result<Vec<FocusSession>, String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     
-    let mut query_builder = QueryBuilder::new("SELECT * FROM focus_sessions WHERE 1=1");
+    // Orphaned sessions are listed by `get_orphaned_focus_sessions` until resolved.
+    let mut query_builder = QueryBuilder::new("SELECT * FROM focus_sessions WHERE orphaned = FALSE");
     
     if let Some(start) = start_date {
         query_builder.push(" AND start_time >= ").push_bind(start);
@@ -29,57 +40,201 @@ pub async fn get_focus_sessions(
     query_builder.push(" ORDER BY start_time DESC");
     
     let query = query_builder.build_query_as::<FocusSession>();
-    let sessions = query.fetch_all(pool).await.map_err(|e| e.to_string())?;
+    let mut sessions = query.fetch_all(pool).await.map_err(|e| e.to_string())?;
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    focus::load_tags(&mut conn, &mut sessions).await.map_err(|e| e.to_string())?;
     
     Ok(sessions)
 }
 
+/// Focused time per project, tag or todo, for sessions started in the range.
+#[tauri::command]
+pub async fn get_focus_totals(
+    state: State<'_, AppState>,
+    group_by: FocusGrouping,
+    start_date: Option<String>,
+    end_date: Option<String>,
+) -> Result<Vec<FocusTotal>, FocusError> {
+    let db = state.db().await?;
+    let mut conn = db.pool().acquire().await?;
+    focus::totals(&mut conn, group_by, start_date, end_date).await
+}
+
+/// Fails with `already_active` while another session, or a Pomodoro phase, runs.
 #[tauri::command]
 pub async fn start_focus_session(
     state: State<'_, AppState>,
-) -> Result<i64, String> {
-    let db = state.db.lock().await;
-    let pool = db.pool();
-    
-    let result = sqlx::query(
-        "INSERT INTO focus_sessions (start_time) VALUES (?)")
-        .bind(Utc::now())
-        .execute(pool)
-        .await
-        .map_err(|e| e.to_string())?;
-    
-    Ok(result.last_insert_rowid())
+    session: Option<NewFocusSession>,
+) -> Result<i64, FocusError> {
+    let db = state.db().await?;
+    let mut tx = db.pool().begin().await?;
+    let session_id = focus::start(&mut tx, Utc::now(), None, &session.unwrap_or_default()).await?;
+    tx.commit().await?;
+    Ok(session_id)
+}
+
+/// The running session, if any, so the frontend can pick it up after a reload.
+#[tauri::command]
+pub async fn get_active_focus_session(
+    state: State<'_, AppState>,
+) -> Result<Option<ActiveFocusSession>, FocusError> {
+    let db = state.db().await?;
+    let mut conn = db.pool().acquire().await?;
+    focus::active(&mut conn).await
 }
 
+/// Records the session's net focused time; pauses don't count.
 #[tauri::command]
 pub async fn end_focus_session(
     state: State<'_, AppState>,
     session_id: i64,
-) -> Result<(), String> {
-    let db = state.db.lock().await;
-    let pool = db.pool();
+) -> Result<(), FocusError> {
+    let db = state.db().await?;
+    let mut tx = db.pool().begin().await?;
     
-    let end_time = Utc::now();
+    let session = focus::get_session(&mut tx, session_id).await?;
+    if session.cycle_id.is_some() {
+        return Err(FocusError::PomodoroPhase);
+    }
+    focus::end(&mut tx, session_id, Utc::now()).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn pause_focus_session(
+    state: State<'_, AppState>,
+    session_id: i64,
+) -> Result<(), FocusError> {
+    let db = state.db().await?;
+    let mut tx = db.pool().begin().await?;
+    focus::pause(&mut tx, session_id, Utc::now()).await?;
+    tx.commit().await?;
+    // A paused Pomodoro phase stops counting down.
+    state.pomodoro.notify_changed();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn resume_focus_session(
+    state: State<'_, AppState>,
+    session_id: i64,
+) -> Result<(), FocusError> {
+    let db = state.db().await?;
+    let mut tx = db.pool().begin().await?;
+    focus::resume(&mut tx, session_id, Utc::now()).await?;
+    tx.commit().await?;
+    state.pomodoro.notify_changed();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_focus_session_pauses(
+    state: State<'_, AppState>,
+    session_id: i64,
+) -> Result<Vec<FocusSessionPause>, FocusError> {
+    let db = state.db().await?;
+    let mut conn = db.pool().acquire().await?;
+    focus::pauses(&mut conn, session_id).await
+}
+
+#[tauri::command]
+pub async fn get_orphaned_focus_sessions(
+    state: State<'_, AppState>,
+) -> Result<Vec<FocusSession>, String> {
+    let db = state.db().await?;
     
-    // Get start time to calculate duration
-    let session: FocusSession = sqlx::query_as(
-        "SELECT * FROM focus_sessions WHERE id = ?")
-        .bind(session_id)
-        .fetch_one(pool)
+    let sessions = sqlx::query_as::<_, FocusSession>(
+        "SELECT * FROM focus_sessions WHERE orphaned = TRUE ORDER BY start_time DESC")
+        .fetch_all(db.pool())
         .await
         .map_err(|e| e.to_string())?;
     
-    let duration = end_time.timestamp() - session.start_time.timestamp();
-    
-    sqlx::query(
-        "UPDATE focus_sessions SET end_time = ?, duration = ? WHERE id = ?")
-        .bind(end_time)
-        .bind(duration)
-        .bind(session_id)
-        .execute(pool)
+    Ok(sessions)
+}
+
+/// Ends an orphaned session at `end_time`; without one the session is discarded.
+#[tauri::command]
+pub async fn resolve_orphaned_session(
+    state: State<'_, AppState>,
+    id: i64,
+    end_time: Option<DateTime<Utc>>,
+) -> Result<(), FocusError> {
+    let db = state.db().await?;
+    let mut conn = db.pool().acquire().await?;
+    focus::resolve_orphaned(&mut conn, id, end_time).await
+}
+
+#[tauri::command]
+pub async fn get_pomodoro_settings(
+    state: State<'_, AppState>,
+) -> Result<PomodoroSettings, String> {
+    let db = state.db().await?;
+    let settings = Database::get_setting(db.pool(), POMODORO_SETTINGS)
         .await
         .map_err(|e| e.to_string())?;
-    
+    Ok(settings.unwrap_or_default())
+}
+
+/// Applies from the next cycle on; a running cycle keeps its lengths.
+#[tauri::command]
+pub async fn set_pomodoro_settings(
+    state: State<'_, AppState>,
+    settings: PomodoroSettings,
+) -> Result<(), String> {
+    pomodoro::validate_settings(&settings).map_err(|e| e.to_string())?;
+    let db = state.db().await?;
+    Database::set_setting(db.pool(), POMODORO_SETTINGS, &settings)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_pomodoro_state(
+    state: State<'_, AppState>,
+) -> Result<Option<PomodoroState>, String> {
+    let db = state.db().await?;
+    pomodoro::active_state(db.pool()).await.map_err(|e| e.to_string())
+}
+
+/// Starts a cycle with the saved settings unless others are passed in.
+#[tauri::command]
+pub async fn start_pomodoro(
+    state: State<'_, AppState>,
+    settings: Option<PomodoroSettings>,
+) -> Result<PomodoroState, String> {
+    let db = state.db().await?;
+    let settings = match settings {
+        Some(settings) => settings,
+        None => Database::get_setting(db.pool(), POMODORO_SETTINGS)
+            .await
+            .map_err(|e| e.to_string())?
+            .unwrap_or_default(),
+    };
+    let started = pomodoro::start(db.pool(), &settings).await.map_err(|e| e.to_string())?;
+    state.pomodoro.notify_changed();
+    Ok(started)
+}
+
+/// Ends the running phase early. Returns the phase that follows, or `None`
+/// when the long break was skipped and the cycle is over.
+#[tauri::command]
+pub async fn skip_pomodoro_phase(
+    state: State<'_, AppState>,
+) -> Result<Option<PomodoroState>, String> {
+    let db = state.db().await?;
+    let next = pomodoro::skip_phase(db.pool()).await.map_err(|e| e.to_string())?;
+    state.pomodoro.notify_changed();
+    Ok(next)
+}
+
+#[tauri::command]
+pub async fn stop_pomodoro(
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let db = state.db().await?;
+    pomodoro::stop(db.pool()).await.map_err(|e| e.to_string())?;
+    state.pomodoro.notify_changed();
     Ok(())
 }
 
@@ -88,17 +243,18 @@ pub async fn get_diary_entry(
     state: State<'_, AppState>,
     date: String,
 ) -> Result<Option<DiaryEntry>, String> {
+    let encryption = state.encryption().await?;
     let db = state.db.lock().await;
     let pool = db.pool();
     
     let entry = sqlx::query_as::<_, DiaryEntry>(
-        "SELECT * FROM diary_entries WHERE date = ?")
+        "SELECT * FROM diary_entries WHERE date = ? ORDER BY created_at DESC, id DESC LIMIT 1")
         .bind(date)
         .fetch_optional(pool)
         .await
         .map_err(|e| e.to_string())?;
     
-    Ok(entry)
+    entry.map(|e| open_diary_entry(&encryption, e)).transpose()
 }
 
 #[tauri::command]
@@ -106,23 +262,60 @@ pub async fn save_diary_entry(
     state: State<'_, AppState>,
     entry: NewDiaryEntry,
 ) -> Result<i64, String> {
+    let encryption = state.encryption().await?;
     let db = state.db.lock().await;
     let pool = db.pool();
     
     let images_json = entry.images.map(|imgs| serde_json::to_string(&imgs).unwrap());
+    let (title, content) = seal_diary_fields(&encryption, entry.title.as_deref(), &entry.content)?;
+    
+    // Checked and written in one transaction; a trigger rejects a second
+    // entry for the day should another save slip in between anyway.
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let mode: DiaryMode = Database::get_setting(&mut *tx, DIARY_MODE_SETTING)
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+
+    // In one-per-day mode the day's entry is edited in place, keeping its id and created_at.
+    if mode == DiaryMode::OnePerDay {
+        let existing: Option<(i64,)> = sqlx::query_as(
+            "SELECT id FROM diary_entries WHERE date = ? ORDER BY created_at DESC, id DESC LIMIT 1")
+            .bind(entry.date)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some((id,)) = existing {
+            sqlx::query(
+                "UPDATE diary_entries SET title = ?, content = ?, mood = ?, images = ?, encrypted = TRUE, updated_at = ?
+                 WHERE id = ?")
+                .bind(title)
+                .bind(content)
+                .bind(entry.mood)
+                .bind(images_json)
+                .bind(Utc::now())
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+            tx.commit().await.map_err(|e| e.to_string())?;
+            return Ok(id);
+        }
+    }
     
     let result = sqlx::query(
-        "INSERT OR REPLACE INTO diary_entries (date, title, content, mood, images, updated_at) 
-         VALUES (?, ?, ?, ?, ?, ?)")
+        "INSERT INTO diary_entries (date, title, content, mood, images, encrypted, updated_at) 
+         VALUES (?, ?, ?, ?, ?, TRUE, ?)")
         .bind(entry.date)
-        .bind(entry.title)
-        .bind(entry.content)
+        .bind(title)
+        .bind(content)
         .bind(entry.mood)
         .bind(images_json)
         .bind(Utc::now())
-        .execute(pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
     
     Ok(result.last_insert_rowid())
 }
@@ -133,6 +326,7 @@ pub async fn get_diary_entries_by_month(
     year: i32,
     month: u32,
 ) -> Result<Vec<DiaryEntry>, String> {
+    let encryption = state.encryption().await?;
     let db = state.db.lock().await;
     let pool = db.pool();
     
@@ -145,20 +339,21 @@ pub async fn get_diary_entries_by_month(
     };
     
     let entries = sqlx::query_as::<_, DiaryEntry>(
-        "SELECT * FROM diary_entries WHERE date >= ? AND date < ? ORDER BY date DESC")
+        "SELECT * FROM diary_entries WHERE date >= ? AND date < ? ORDER BY date DESC, created_at DESC")
         .bind(start_date)
         .bind(end_date)
         .fetch_all(pool)
         .await
         .map_err(|e| e.to_string())?;
     
-    Ok(entries)
+    open_diary_entries(&encryption, entries)
 }
 
 #[tauri::command]
 pub async fn get_all_diary_entries(
     state: State<'_, AppState>,
 ) -> Result<Vec<DiaryEntry>, String> {
+    let encryption = state.encryption().await?;
     let db = state.db.lock().await;
     let pool = db.pool();
 
@@ -168,7 +363,7 @@ pub async fn get_all_diary_entries(
         .await
         .map_err(|e| e.to_string())?;
 
-    Ok(entries)
+    open_diary_entries(&encryption, entries)
 }
 
 #[tauri::command]
@@ -176,7 +371,7 @@ pub async fn get_todos(
     state: State<'_, AppState>,
     completed: Option<bool>,
 ) -> Result<Vec<Todo>, String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     
     let mut query_builder = QueryBuilder::new("SELECT * FROM todos WHERE 1=1");
@@ -198,7 +393,7 @@ pub async fn create_todo(
     state: State<'_, AppState>,
     todo: NewTodo,
 ) -> Result<i64, String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     
     let result = sqlx::query(
@@ -210,6 +405,7 @@ pub async fn create_todo(
         .execute(pool)
         .await
         .map_err(|e| e.to_string())?;
+    state.todo_reminders.notify_changed();
     
     Ok(result.last_insert_rowid())
 }
@@ -219,7 +415,7 @@ pub async fn update_todo(
     state: State<'_, AppState>,
     todo: UpdateTodo,
 ) -> Result<(), String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     
     let mut query_builder = QueryBuilder::new("UPDATE todos SET updated_at = ?");
@@ -242,12 +438,15 @@ pub async fn update_todo(
     }
     
     if let Some(due_date) = &todo.due_date {
+        // A new due date gets its own reminder.
         query_builder.push(", due_date = ").push_bind(due_date);
+        query_builder.push(", reminded_at = NULL");
     }
     
     query_builder.push(" WHERE id = ?").push_bind(todo.id);
     
     query_builder.build().execute(pool).await.map_err(|e| e.to_string())?;
+    state.todo_reminders.notify_changed();
     
     Ok(())
 }
@@ -257,7 +456,7 @@ pub async fn delete_todo(
     state: State<'_, AppState>,
     todo_id: i64,
 ) -> Result<(), String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     
     sqlx::query("DELETE FROM todos WHERE id = ?")
@@ -265,6 +464,7 @@ pub async fn delete_todo(
         .execute(pool)
         .await
         .map_err(|e| e.to_string())?;
+    state.todo_reminders.notify_changed();
     
     Ok(())
 }
@@ -273,7 +473,7 @@ pub async fn delete_todo(
 pub async fn get_alarms(
     state: State<'_, AppState>,
 ) -> Result<Vec<Alarm>, String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     
     let alarms = sqlx::query_as::<_, Alarm>(
@@ -290,7 +490,7 @@ pub async fn delete_diary_entry(
     state: State<'_, AppState>,
     date: String,
 ) -> Result<(), String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     sqlx::query("DELETE FROM diary_entries WHERE date = ?")
         .bind(date)
@@ -305,15 +505,16 @@ pub async fn get_diary_entries_by_date(
     state: State<'_, AppState>,
     date: String,
 ) -> Result<Vec<DiaryEntry>, String> {
+    let encryption = state.encryption().await?;
     let db = state.db.lock().await;
     let pool = db.pool();
     let entries = sqlx::query_as::<_, DiaryEntry>(
-        "SELECT * FROM diary_entries WHERE date = ? ORDER BY created_at DESC")
+        "SELECT * FROM diary_entries WHERE date = ? ORDER BY created_at DESC, id DESC")
         .bind(date)
         .fetch_all(pool)
         .await
         .map_err(|e| e.to_string())?;
-    Ok(entries)
+    open_diary_entries(&encryption, entries)
 }
 
 #[tauri::command]
@@ -321,6 +522,7 @@ pub async fn get_diary_entry_by_id(
     state: State<'_, AppState>,
     id: i64,
 ) -> Result<Option<DiaryEntry>, String> {
+    let encryption = state.encryption().await?;
     let db = state.db.lock().await;
     let pool = db.pool();
     let entry = sqlx::query_as::<_, DiaryEntry>(
@@ -329,7 +531,7 @@ pub async fn get_diary_entry_by_id(
         .fetch_optional(pool)
         .await
         .map_err(|e| e.to_string())?;
-    Ok(entry)
+    entry.map(|e| open_diary_entry(&encryption, e)).transpose()
 }
 
 #[tauri::command]
@@ -337,12 +539,26 @@ pub async fn update_diary_entry(
     state: State<'_, AppState>,
     entry: UpdateDiaryEntry,
 ) -> Result<(), String> {
+    let encryption = state.encryption().await?;
     let db = state.db.lock().await;
     let pool = db.pool();
-    let mut qb = QueryBuilder::new("UPDATE diary_entries SET updated_at = ");
+    // Title and content are sealed together, so merge the patch with the stored row first.
+    let current = sqlx::query_as::<_, DiaryEntry>(
+        "SELECT * FROM diary_entries WHERE id = ?")
+        .bind(entry.id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(current) = current else { return Ok(()) };
+    let current = open_diary_entry(&encryption, current)?;
+    let title = entry.title.or(current.title);
+    let content = entry.content.unwrap_or(current.content);
+    let (title, content) = seal_diary_fields(&encryption, title.as_deref(), &content)?;
+
+    let mut qb = QueryBuilder::new("UPDATE diary_entries SET encrypted = TRUE, updated_at = ");
     qb.push_bind(Utc::now());
-    if let Some(title) = &entry.title { qb.push(", title = ").push_bind(title); }
-    if let Some(content) = &entry.content { qb.push(", content = ").push_bind(content); }
+    qb.push(", title = ").push_bind(title);
+    qb.push(", content = ").push_bind(content);
     if let Some(mood) = entry.mood { qb.push(", mood = ").push_bind(mood); }
     if let Some(images) = &entry.images {
         let images_json = serde_json::to_string(images).unwrap_or_default();
@@ -358,7 +574,7 @@ pub async fn delete_diary_entry_by_id(
     state: State<'_, AppState>,
     id: i64,
 ) -> Result<(), String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     sqlx::query("DELETE FROM diary_entries WHERE id = ?")
         .bind(id)
@@ -368,6 +584,66 @@ pub async fn delete_diary_entry_by_id(
     Ok(())
 }
 
+#[tauri::command]
+pub async fn get_diary_mode(
+    state: State<'_, AppState>,
+) -> Result<DiaryMode, String> {
+    let db = state.db().await?;
+    db.diary_mode().await.map_err(|e| e.to_string())
+}
+
+/// Switching to one-per-day folds existing same-date entries together; the
+/// returned report says what was merged.
+#[tauri::command]
+pub async fn set_diary_mode(
+    state: State<'_, AppState>,
+    mode: DiaryMode,
+) -> Result<DiaryMergeReport, String> {
+    let encryption = state.encryption().await?;
+    let db = state.db.lock().await;
+    let mut tx = db.pool().begin().await.map_err(|e| e.to_string())?;
+
+    let report = if mode == DiaryMode::OnePerDay {
+        Database::merge_duplicate_diary_entries(&mut tx, Some(&encryption))
+            .await
+            .map_err(|e| e.to_string())?
+    } else {
+        DiaryMergeReport::default()
+    };
+    Database::set_setting(&mut *tx, DIARY_MODE_SETTING, &mode)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(report)
+}
+
+fn seal_diary_fields(
+    encryption: &Encryption,
+    title: Option<&str>,
+    content: &str,
+) -> Result<(Option<String>, String), String> {
+    let title = title.map(|t| encryption.encrypt(t)).transpose().map_err(|e| e.to_string())?;
+    let content = encryption.encrypt(content).map_err(|e| e.to_string())?;
+    Ok((title, content))
+}
+
+fn open_diary_entry(encryption: &Encryption, mut entry: DiaryEntry) -> Result<DiaryEntry, String> {
+    if entry.encrypted {
+        entry.title = entry.title
+            .map(|t| encryption.decrypt(&t))
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        entry.content = encryption.decrypt(&entry.content).map_err(|e| e.to_string())?;
+        entry.encrypted = false;
+    }
+    Ok(entry)
+}
+
+fn open_diary_entries(encryption: &Encryption, entries: Vec<DiaryEntry>) -> Result<Vec<DiaryEntry>, String> {
+    entries.into_iter().map(|e| open_diary_entry(encryption, e)).collect()
+}
+
 #[tauri::command]
 pub fn load_file_base64(path: String) -> Result<String, String> {
     let p = Path::new(&path);
@@ -391,79 +667,249 @@ pub fn load_file_base64(path: String) -> Result<String, String> {
 pub async fn create_alarm(
     state: State<'_, AppState>,
     alarm: NewAlarm,
-) -> Result<i64, String> {
-    let db = state.db.lock().await;
+) -> Result<i64, AlarmError> {
+    let mut schedule = AlarmSchedule::parse_upcoming(
+        &alarm.time,
+        alarm.days.as_deref().unwrap_or_default(),
+        alarm.date.as_deref(),
+    )?;
+    schedule.set_exceptions(alarm.exceptions.unwrap_or_default())?;
+    if let Some(minutes) = alarm.snooze_minutes {
+        validate_snooze_minutes(minutes)?;
+    }
+    let db = state.db().await?;
     let pool = db.pool();
     
-    let days_json = alarm.days.map(|days| serde_json::to_string(&days).unwrap());
-    
     let result = sqlx::query(
-        "INSERT INTO alarms (time, days, label, sound_path) VALUES (?, ?, ?, ?)")
-        .bind(alarm.time)
-        .bind(days_json)
+        "INSERT INTO alarms (time, days, date, exceptions, label, sound_path, snooze_minutes, max_snoozes)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+        .bind(schedule.time_string())
+        .bind(schedule.days_json())
+        .bind(schedule.date)
+        .bind(schedule.exceptions_json())
         .bind(alarm.label)
         .bind(alarm.sound_path)
+        .bind(alarm.snooze_minutes)
+        .bind(alarm.max_snoozes)
         .execute(pool)
-        .await
-        .map_err(|e| e.to_string())?;
+        .await?;
+    state.alarm_scheduler.notify_changed();
     
     Ok(result.last_insert_rowid())
 }
 
+/// Fields left out are kept. The resulting schedule is validated as a whole,
+/// so e.g. adding `days` to a one-off alarm requires clearing its `date`.
+/// Changing the time, days or date drops a skipped occurrence.
 #[tauri::command]
 pub async fn update_alarm(
     state: State<'_, AppState>,
     alarm: UpdateAlarm,
-) -> Result<(), String> {
-    let db = state.db.lock().await;
+) -> Result<(), AlarmError> {
+    let db = state.db().await?;
     let pool = db.pool();
     
-    let mut query_builder = QueryBuilder::new("UPDATE alarms SET");
-    let mut first = true;
+    let existing = sqlx::query_as::<_, Alarm>("SELECT * FROM alarms WHERE id = ?")
+        .bind(alarm.id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AlarmError::NotFound(alarm.id))?;
     
-    if let Some(time) = &alarm.time {
-        if !first { query_builder.push(", "); }
-        query_builder.push(" time = ").push_bind(time);
-        first = false;
+    let schedule_changed = alarm.time.is_some() || alarm.days.is_some() || alarm.date.is_some();
+    let time = alarm.time.unwrap_or(existing.time);
+    let days = match alarm.days {
+        Some(days) => days,
+        None => existing.days.as_deref()
+            .map(serde_json::from_str)
+            .transpose()
+            .map_err(|_| AlarmError::InvalidDay(existing.days.clone().unwrap_or_default()))?
+            .unwrap_or_default(),
+    };
+    let date = match alarm.date {
+        Some(date) => date,
+        None => existing.date.map(|date| date.format("%Y-%m-%d").to_string()),
+    };
+    let mut schedule = if schedule_changed {
+        AlarmSchedule::parse_upcoming(&time, &days, date.as_deref())?
+    } else {
+        AlarmSchedule::parse(&time, &days, date.as_deref())?
+    };
+    let exceptions = match alarm.exceptions {
+        Some(exceptions) => exceptions,
+        None => existing.exceptions.as_deref()
+            .map(serde_json::from_str)
+            .transpose()
+            .map_err(|_| AlarmError::Storage(
+                format!("Invalid alarm exceptions: {}", existing.exceptions.clone().unwrap_or_default())))?
+            .unwrap_or_default(),
+    };
+    schedule.set_exceptions(exceptions)?;
+    let skipped_occurrence = if schedule_changed { None } else { existing.skipped_occurrence };
+    let snooze_minutes = alarm.snooze_minutes.unwrap_or(existing.snooze_minutes);
+    if let Some(minutes) = snooze_minutes {
+        validate_snooze_minutes(minutes)?;
     }
     
-    if let Some(days) = &alarm.days {
-        if !first { query_builder.push(", "); }
-        let days_json = serde_json::to_string(days).unwrap();
-        query_builder.push(" days = ").push_bind(days_json);
-        first = false;
-    }
+    sqlx::query(
+        "UPDATE alarms SET time = ?, days = ?, date = ?, exceptions = ?, skipped_occurrence = ?, enabled = ?,
+         label = ?, sound_path = ?, snooze_minutes = ?, max_snoozes = ? WHERE id = ?")
+        .bind(schedule.time_string())
+        .bind(schedule.days_json())
+        .bind(schedule.date)
+        .bind(schedule.exceptions_json())
+        .bind(skipped_occurrence)
+        .bind(alarm.enabled.unwrap_or(existing.enabled))
+        .bind(alarm.label.or(existing.label))
+        .bind(alarm.sound_path.or(existing.sound_path))
+        .bind(snooze_minutes)
+        .bind(alarm.max_snoozes.unwrap_or(existing.max_snoozes))
+        .bind(alarm.id)
+        .execute(pool)
+        .await?;
+    state.alarm_scheduler.notify_changed();
     
-    if let Some(enabled) = alarm.enabled {
-        if !first { query_builder.push(", "); }
-        query_builder.push(" enabled = ").push_bind(enabled);
-        first = false;
-    }
+    Ok(())
+}
+
+/// Skips the next occurrence without disabling the alarm and returns the
+/// skipped time. Skipping again moves the skip to the occurrence after it.
+#[tauri::command]
+pub async fn skip_next_alarm_occurrence(
+    state: State<'_, AppState>,
+    id: i64,
+) -> Result<DateTime<Utc>, AlarmError> {
+    let db = state.db().await?;
+    let pool = db.pool();
     
-    if let Some(label) = &alarm.label {
-        if !first { query_builder.push(", "); }
-        query_builder.push(" label = ").push_bind(label);
-        first = false;
-    }
+    let alarm = sqlx::query_as::<_, Alarm>("SELECT * FROM alarms WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AlarmError::NotFound(id))?;
+    let schedule = alarm.schedule()?;
+    // With the current skip still in place this finds the occurrence after it.
+    let next = schedule.next_after(&Local, Local::now())
+        .ok_or(AlarmError::NoUpcomingOccurrence(id))?
+        .with_timezone(&Utc);
     
-    if let Some(sound_path) = &alarm.sound_path {
-        if !first { query_builder.push(", "); }
-        query_builder.push(" sound_path = ").push_bind(sound_path);
-    }
+    sqlx::query("UPDATE alarms SET skipped_occurrence = ? WHERE id = ?")
+        .bind(next)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    state.alarm_scheduler.notify_changed();
     
-    query_builder.push(" WHERE id = ?").push_bind(alarm.id);
+    Ok(next)
+}
+
+#[tauri::command]
+pub async fn cancel_alarm_skip(
+    state: State<'_, AppState>,
+    id: i64,
+) -> Result<(), AlarmError> {
+    let db = state.db().await?;
     
-    query_builder.build().execute(pool).await.map_err(|e| e.to_string())?;
+    let result = sqlx::query("UPDATE alarms SET skipped_occurrence = NULL WHERE id = ?")
+        .bind(id)
+        .execute(db.pool())
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(AlarmError::NotFound(id));
+    }
+    state.alarm_scheduler.notify_changed();
     
     Ok(())
 }
 
+/// Returns when the alarm will ring again. `minutes` overrides the alarm's
+/// own snooze length for this snooze only.
+#[tauri::command]
+pub async fn snooze_alarm(
+    state: State<'_, AppState>,
+    id: i64,
+    minutes: Option<u32>,
+) -> Result<DateTime<Utc>, AlarmError> {
+    // Alarms ring while locked, so they can be silenced while locked too.
+    let db = state.db.lock().await;
+    state.alarm_scheduler.snooze(&db, id, minutes).await
+}
+
+#[tauri::command]
+pub async fn dismiss_alarm(
+    state: State<'_, AppState>,
+    id: i64,
+) -> Result<(), AlarmError> {
+    let db = state.db.lock().await;
+    state.alarm_scheduler.dismiss(&db, id).await
+}
+
+/// Ring history, newest first; for one alarm or all of them.
+#[tauri::command]
+pub async fn get_alarm_events(
+    state: State<'_, AppState>,
+    alarm_id: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Vec<AlarmEvent>, String> {
+    let db = state.db().await?;
+    let pool = db.pool();
+    
+    let events = sqlx::query_as::<_, AlarmEvent>(
+        "SELECT * FROM alarm_events WHERE (? IS NULL OR alarm_id = ?) ORDER BY occurred_at DESC, id DESC LIMIT ?")
+        .bind(alarm_id)
+        .bind(alarm_id)
+        .bind(limit.unwrap_or(100))
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    Ok(events)
+}
+
+#[tauri::command]
+pub async fn get_alarm_snooze_defaults(
+    state: State<'_, AppState>,
+) -> Result<SnoozeDefaults, String> {
+    let db = state.db().await?;
+    let defaults = Database::get_setting(db.pool(), ALARM_SNOOZE_DEFAULTS)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(defaults.unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn set_alarm_snooze_defaults(
+    state: State<'_, AppState>,
+    defaults: SnoozeDefaults,
+) -> Result<(), AlarmError> {
+    validate_snooze_minutes(defaults.snooze_minutes)?;
+    let db = state.db().await?;
+    Database::set_setting(db.pool(), ALARM_SNOOZE_DEFAULTS, &defaults)
+        .await
+        .map_err(|e| AlarmError::Storage(e.to_string()))
+}
+
+/// The next `count` times any enabled alarm rings, from `from` (default now),
+/// in the local time zone.
+#[tauri::command]
+pub async fn get_next_alarm_occurrences(
+    state: State<'_, AppState>,
+    from: Option<DateTime<Utc>>,
+    count: usize,
+) -> Result<Vec<AlarmOccurrence>, AlarmError> {
+    let db = state.db().await?;
+    let alarms = sqlx::query_as::<_, Alarm>("SELECT * FROM alarms WHERE enabled = TRUE")
+        .fetch_all(db.pool())
+        .await?;
+    let from = from.map(|from| from.with_timezone(&Local)).unwrap_or_else(Local::now);
+    Ok(upcoming_occurrences(&alarms, &Local, from, count))
+}
+
 #[tauri::command]
 pub async fn delete_alarm(
     state: State<'_, AppState>,
     alarm_id: i64,
 ) -> Result<(), String> {
-    let db = state.db.lock().await;
+    let db = state.db().await?;
     let pool = db.pool();
     
     sqlx::query("DELETE FROM alarms WHERE id = ?")
@@ -471,10 +917,223 @@ pub async fn delete_alarm(
         .execute(pool)
         .await
         .map_err(|e| e.to_string())?;
+    state.alarm_scheduler.notify_changed();
     
     Ok(())
 }
 
+// Timers and the stopwatch hold no user data, so they work while locked.
+
+#[tauri::command]
+pub async fn get_timers(
+    state: State<'_, AppState>,
+) -> Result<Vec<TimerSnapshot>, String> {
+    Ok(state.clock.timers())
+}
+
+#[tauri::command]
+pub async fn create_timer(
+    state: State<'_, AppState>,
+    label: Option<String>,
+    seconds: u64,
+    start: Option<bool>,
+) -> Result<TimerSnapshot, String> {
+    state.clock.create_timer(label, seconds, start.unwrap_or(true)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn start_timer(
+    state: State<'_, AppState>,
+    id: u64,
+) -> Result<TimerSnapshot, String> {
+    state.clock.start_timer(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn pause_timer(
+    state: State<'_, AppState>,
+    id: u64,
+) -> Result<TimerSnapshot, String> {
+    state.clock.pause_timer(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn reset_timer(
+    state: State<'_, AppState>,
+    id: u64,
+) -> Result<TimerSnapshot, String> {
+    state.clock.reset_timer(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn delete_timer(
+    state: State<'_, AppState>,
+    id: u64,
+) -> Result<(), String> {
+    state.clock.delete_timer(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_stopwatch(
+    state: State<'_, AppState>,
+) -> Result<StopwatchSnapshot, String> {
+    Ok(state.clock.stopwatch())
+}
+
+#[tauri::command]
+pub async fn start_stopwatch(
+    state: State<'_, AppState>,
+) -> Result<StopwatchSnapshot, String> {
+    state.clock.start_stopwatch().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn pause_stopwatch(
+    state: State<'_, AppState>,
+) -> Result<StopwatchSnapshot, String> {
+    state.clock.pause_stopwatch().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn reset_stopwatch(
+    state: State<'_, AppState>,
+) -> Result<StopwatchSnapshot, String> {
+    state.clock.reset_stopwatch().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn lap_stopwatch(
+    state: State<'_, AppState>,
+) -> Result<Lap, String> {
+    state.clock.lap_stopwatch().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_world_clocks(
+    state: State<'_, AppState>,
+) -> Result<Vec<WorldClockTime>, String> {
+    let db = state.db().await?;
+    let pool = db.pool();
+    
+    let clocks = sqlx::query_as::<_, WorldClock>("SELECT * FROM world_clocks ORDER BY position, id")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    let now = Utc::now();
+    clocks.iter()
+        .map(|clock| clock.time_at(now))
+        .collect::<Result<_>>()
+        .map_err(|e| e.to_string())
+}
+
+/// New clocks go to the end of the list.
+#[tauri::command]
+pub async fn add_world_clock(
+    state: State<'_, AppState>,
+    clock: NewWorldClock,
+) -> Result<i64, String> {
+    parse_time_zone(&clock.time_zone).map_err(|e| e.to_string())?;
+    let db = state.db().await?;
+    let pool = db.pool();
+    
+    let result = sqlx::query(
+        "INSERT INTO world_clocks (time_zone, label, position)
+         VALUES (?, ?, (SELECT COALESCE(MAX(position), -1) + 1 FROM world_clocks))")
+        .bind(&clock.time_zone)
+        .bind(clock.label)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    Ok(result.last_insert_rowid())
+}
+
+#[tauri::command]
+pub async fn update_world_clock(
+    state: State<'_, AppState>,
+    clock: UpdateWorldClock,
+) -> Result<(), String> {
+    let db = state.db().await?;
+    let pool = db.pool();
+    
+    let existing = sqlx::query_as::<_, WorldClock>("SELECT * FROM world_clocks WHERE id = ?")
+        .bind(clock.id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("World clock {} not found", clock.id))?;
+    
+    sqlx::query("UPDATE world_clocks SET label = ?, position = ? WHERE id = ?")
+        .bind(clock.label.unwrap_or(existing.label))
+        .bind(clock.position.unwrap_or(existing.position))
+        .bind(clock.id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_world_clock(
+    state: State<'_, AppState>,
+    id: i64,
+) -> Result<(), String> {
+    let db = state.db().await?;
+    
+    sqlx::query("DELETE FROM world_clocks WHERE id = ?")
+        .bind(id)
+        .execute(db.pool())
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_time_zones() -> Vec<&'static str> {
+    time_zone_names()
+}
+
+/// Windows on `date` (today by default) when it is working hours in every
+/// saved zone and, unless `include_local` is false, here as well.
+#[tauri::command]
+pub async fn plan_meeting(
+    state: State<'_, AppState>,
+    date: Option<String>,
+    work_start: Option<String>,
+    work_end: Option<String>,
+    min_minutes: Option<u32>,
+    include_local: Option<bool>,
+) -> Result<Vec<MeetingWindow>, String> {
+    let date = match date {
+        Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|e| e.to_string())?,
+        None => Local::now().date_naive(),
+    };
+    let work_start = parse_work_time(work_start.as_deref().unwrap_or(DEFAULT_WORK_START)).map_err(|e| e.to_string())?;
+    let work_end = parse_work_time(work_end.as_deref().unwrap_or(DEFAULT_WORK_END)).map_err(|e| e.to_string())?;
+    
+    let clocks = {
+        let db = state.db().await?;
+        sqlx::query_as::<_, WorldClock>("SELECT * FROM world_clocks ORDER BY position, id")
+            .fetch_all(db.pool())
+            .await
+            .map_err(|e| e.to_string())?
+    };
+    let mut participants = Vec::new();
+    if include_local.unwrap_or(true) {
+        participants.push(Participant { zone: Zone::Local, time_zone: "local".to_string(), label: None });
+    }
+    for clock in clocks {
+        let tz = parse_time_zone(&clock.time_zone).map_err(|e| e.to_string())?;
+        participants.push(Participant { zone: Zone::Named(tz), time_zone: clock.time_zone, label: clock.label });
+    }
+    
+    plan_meeting_windows(&participants, &Local, date, work_start, work_end, min_minutes.unwrap_or(30) as i64)
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn get_theme(
     _state: State<'_, AppState>,
@@ -532,7 +1191,27 @@ pub fn resolve_resource_path(name: String) -> Result<String, String> {
 }
 
 #[tauri::command]
-pub fn set_do_not_disturb(enabled: bool) -> Result<(), String> {
+pub async fn get_do_not_disturb(
+    state: State<'_, AppState>,
+) -> Result<bool, String> {
+    Ok(state.notifications.do_not_disturb())
+}
+
+/// Toggles the app's do-not-disturb state, which holds back backend
+/// notifications, and on Windows mirrors it into Focus Assist.
+#[tauri::command]
+pub async fn set_do_not_disturb(
+    state: State<'_, AppState>,
+    enabled: bool,
+) -> Result<(), String> {
+    // Not user data, so this works while locked too.
+    let db = state.db.lock().await;
+    Database::set_setting(db.pool(), DO_NOT_DISTURB_SETTING, &enabled)
+        .await
+        .map_err(|e| e.to_string())?;
+    drop(db);
+    state.notifications.set_do_not_disturb(enabled).map_err(|e| e.to_string())?;
+
     #[cfg(target_os = "windows")]
     {
         use winreg::enums::*;
@@ -547,3 +1226,280 @@ pub fn set_do_not_disturb(enabled: bool) -> Result<(), String> {
     }
     Ok(())
 }
+
+#[tauri::command]
+pub async fn get_lock_status(
+    state: State<'_, AppState>,
+) -> Result<LockStatus, String> {
+    let passphrase_enabled = Encryption::passphrase_enabled().map_err(|e| e.to_string())?;
+    let locked = state.encryption.read().await.is_none();
+    Ok(LockStatus { passphrase_enabled, locked })
+}
+
+#[tauri::command]
+pub async fn unlock(
+    state: State<'_, AppState>,
+    passphrase: String,
+) -> Result<(), String> {
+    let mut slot = state.encryption.write().await;
+    if slot.is_some() {
+        return Ok(());
+    }
+
+    // Argon2 is deliberately slow; keep it off the async workers.
+    let (key, passphrase) = tokio::task::spawn_blocking(move || {
+        Encryption::unlock_key(&passphrase).map(|key| (key, passphrase))
+    })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    let encryption = Encryption::new(&key).map_err(|e| e.to_string())?;
+
+    let db = state.db.lock().await;
+    let encryption = db.recover_interrupted_rotation(encryption, Some(&passphrase))
+        .await
+        .map_err(|e| e.to_string())?;
+    db.encrypt_plaintext_diary_entries(&encryption).await.map_err(|e| e.to_string())?;
+
+    *slot = Some(encryption);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn lock(
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if !Encryption::passphrase_enabled().map_err(|e| e.to_string())? {
+        return Err("Passphrase mode is not enabled".to_string());
+    }
+    state.encryption.write().await.take();
+    Ok(())
+}
+
+/// Enables passphrase mode, or changes the passphrase. Changing it requires
+/// `current_passphrase`.
+#[tauri::command]
+pub async fn set_passphrase(
+    state: State<'_, AppState>,
+    current_passphrase: Option<String>,
+    passphrase: String,
+) -> Result<(), String> {
+    let encryption = state.encryption().await?;
+    let key = encryption.key().to_vec();
+    tokio::task::spawn_blocking(move || {
+        Encryption::enable_passphrase(&key, current_passphrase.as_deref(), &passphrase)
+    })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn rotate_encryption_key(
+    state: State<'_, AppState>,
+    passphrase: Option<String>,
+) -> Result<u64, String> {
+    let _busy = state.backup_manager.try_begin_exclusive()
+        .ok_or("A backup or import is in progress; try again once it has finished")?;
+    let mut slot = state.encryption.write().await;
+    let current = slot.as_ref().ok_or(LOCKED_ERROR)?;
+
+    // In passphrase mode the new key is wrapped with the same passphrase, so
+    // make sure it is the right one before touching any data.
+    let passphrase = if Encryption::passphrase_enabled().map_err(|e| e.to_string())? {
+        let passphrase = passphrase.ok_or("Passphrase is required to rotate the key")?;
+        let current_key = current.key().to_vec();
+        let passphrase = tokio::task::spawn_blocking(move || {
+            match Encryption::unlock_key(&passphrase) {
+                Ok(key) if key == current_key => Ok(passphrase),
+                Ok(_) => Err("Passphrase does not match the active key".to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        })
+            .await
+            .map_err(|e| e.to_string())??;
+        Some(passphrase)
+    } else {
+        None
+    };
+
+    let new_key = Encryption::generate_key();
+    let next = Encryption::new(&new_key).map_err(|e| e.to_string())?;
+    let stage_key = new_key.clone();
+    let stage_passphrase = passphrase.clone();
+    tokio::task::spawn_blocking(move || Encryption::stage_key(&stage_key, stage_passphrase.as_deref()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+    // The old key stays live on disk and in memory until the transaction commits.
+    let db = state.db.lock().await;
+    let rows = match db.reencrypt_diary_entries(current, &next).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            let _ = Encryption::discard_staged_key();
+            return Err(e.to_string());
+        }
+    };
+    // The rows use the new key from here on, so it goes live in memory first.
+    // Should the key files fail to follow, the staged key stays on disk and
+    // startup recovery finishes the rotation.
+    let previous = slot.replace(next).ok_or(LOCKED_ERROR)?;
+    let next = slot.as_ref().ok_or(LOCKED_ERROR)?;
+    Encryption::finish_rotation(&previous, next)
+        .map_err(|e| format!("Diary entries use the new key, but saving it failed ({}); it is saved on the next start", e))?;
+
+    Ok(rows)
+}
+
+#[tauri::command]
+pub async fn get_startup_notices(
+    state: State<'_, AppState>,
+) -> Result<Vec<StartupNotice>, String> {
+    let db = state.db().await?;
+    let pool = db.pool();
+
+    let notices = sqlx::query_as::<_, StartupNotice>(
+        "SELECT id, kind, message, created_at FROM startup_notices WHERE dismissed_at IS NULL ORDER BY created_at ASC")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(notices)
+}
+
+#[tauri::command]
+pub async fn dismiss_startup_notice(
+    state: State<'_, AppState>,
+    id: i64,
+) -> Result<(), String> {
+    let db = state.db().await?;
+    let pool = db.pool();
+
+    sqlx::query("UPDATE startup_notices SET dismissed_at = ? WHERE id = ?")
+        .bind(Utc::now())
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_backup_status(
+    state: State<'_, AppState>,
+) -> Result<BackupStatus, String> {
+    Ok(state.backup_manager.status())
+}
+
+/// Backs up immediately, even if nothing changed since the last backup.
+#[tauri::command]
+pub async fn trigger_backup(
+    state: State<'_, AppState>,
+) -> Result<BackupStatus, String> {
+    state.backup_manager.backup_now(&state.db, &state.encryption, true).await.map_err(|e| e.to_string())?;
+    Ok(state.backup_manager.status())
+}
+
+#[tauri::command]
+pub async fn get_backup_settings(
+    state: State<'_, AppState>,
+) -> Result<BackupSettings, String> {
+    let db = state.db().await?;
+    let settings = Database::get_setting(db.pool(), BACKUP_SETTINGS)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(settings.unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn set_backup_settings(
+    state: State<'_, AppState>,
+    settings: BackupSettings,
+) -> Result<(), String> {
+    if let Some(destination) = settings.destinations.iter().find(|d| !std::path::Path::new(&d.path).is_absolute()) {
+        return Err(format!("Backup destination must be an absolute path: {}", destination.path));
+    }
+    let db = state.db().await?;
+    Database::set_setting(db.pool(), BACKUP_SETTINGS, &settings)
+        .await
+        .map_err(|e| e.to_string())?;
+    state.backup_manager.notify_settings_changed();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_backups(
+    state: State<'_, AppState>,
+) -> Result<Vec<BackupInfo>, String> {
+    state.backup_manager.list_backups().await.map_err(|e| e.to_string())
+}
+
+/// `passphrase` is only needed for backups sealed under an earlier backup passphrase.
+#[tauri::command]
+pub async fn verify_backup(
+    state: State<'_, AppState>,
+    id: String,
+    passphrase: Option<String>,
+) -> Result<(), String> {
+    let encryption = state.encryption().await?;
+    let keys = ArchiveKeys { data_key: &encryption, passphrase: passphrase.as_deref() };
+    state.backup_manager.verify_backup(&id, &keys).await.map_err(|e| e.to_string())
+}
+
+/// Returns the id of the safety backup taken before the restore.
+#[tauri::command]
+pub async fn restore_backup(
+    state: State<'_, AppState>,
+    id: String,
+    passphrase: Option<String>,
+) -> Result<String, String> {
+    let encryption = state.encryption().await?;
+    let mut db = state.db.lock().await;
+    let keys = ArchiveKeys { data_key: &encryption, passphrase: passphrase.as_deref() };
+    let safety_id = state.backup_manager
+        .restore_backup(&mut db, &id, &keys)
+        .await
+        .map_err(|e| e.to_string())?;
+    state.alarm_scheduler.notify_changed();
+    state.pomodoro.notify_changed();
+    state.todo_reminders.notify_changed();
+    Ok(safety_id)
+}
+
+/// Seals new backups with a key derived from `passphrase` instead of the data
+/// key, so they can be restored on another machine. `None` switches back.
+#[tauri::command]
+pub async fn set_backup_passphrase(
+    state: State<'_, AppState>,
+    passphrase: Option<String>,
+) -> Result<(), String> {
+    let encryption = state.encryption().await?;
+    let data_key = Encryption::new(encryption.key()).map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || Encryption::set_backup_passphrase(&data_key, passphrase.as_deref()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// Dry run of the retention policy: lists which backups would be kept and
+/// which deleted. Uses the saved policy unless one is passed in.
+#[tauri::command]
+pub async fn preview_backup_retention(
+    state: State<'_, AppState>,
+    policy: Option<RetentionPolicy>,
+) -> Result<RetentionPlan, String> {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => {
+            let db = state.db().await?;
+            let settings: BackupSettings = Database::get_setting(db.pool(), BACKUP_SETTINGS)
+                .await
+                .map_err(|e| e.to_string())?
+                .unwrap_or_default();
+            settings.retention
+        }
+    };
+    state.backup_manager.plan_retention(&policy).map_err(|e| e.to_string())
+}
@@ -1,7 +1,58 @@
-use sqlx::{SqlitePool, migrate::MigrateDatabase};
+use sqlx::{Executor, Sqlite, SqliteConnection, SqlitePool, migrate::{MigrateDatabase, Migrator}};
+use serde::{Serialize, de::DeserializeOwned};
 use std::path::PathBuf;
 use directories::ProjectDirs;
 use anyhow::Result;
+use crate::encryption::Encryption;
+use crate::models::DiaryMode;
+
+/// Forward-only schema migrations embedded from `migrations/`. Applied
+/// versions and their checksums are tracked in `_sqlx_migrations`, so an
+/// edited migration is rejected instead of silently diverging.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+pub const DIARY_MODE_SETTING: &str = "diary_mode";
+
+/// Separator placed between the contents of diary entries merged into one.
+const DIARY_MERGE_SEPARATOR: &str = "\n\n---\n\n";
+
+/// Tables holding diary text, which is sealed with the data key.
+const DIARY_TABLES: [&str; 2] = ["diary_entries", "diary_entries_archive"];
+
+/// `(id, title, content, mood, images, encrypted)` of a diary row being merged.
+type MergeRow = (i64, Option<String>, String, Option<i32>, Option<String>, bool);
+
+/// Outcome of folding same-date diary entries together.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct DiaryMergeReport {
+    /// Days that had more than one entry.
+    pub dates: u64,
+    /// Entries whose content was merged into the newest entry of their day.
+    pub merged: u64,
+    /// Entries moved to the archive without merging because they were
+    /// encrypted and no key was available.
+    pub archived_only: u64,
+}
+
+impl DiaryMergeReport {
+    pub fn is_empty(&self) -> bool {
+        self.dates == 0
+    }
+
+    pub fn describe(&self) -> String {
+        let mut message = format!(
+            "Found {} day(s) with more than one diary entry. {} entr(ies) were merged into the latest entry of their day; the originals are kept in the diary archive.",
+            self.dates, self.merged,
+        );
+        if self.archived_only > 0 {
+            message.push_str(&format!(
+                " {} encrypted entr(ies) could not be merged and were moved to the diary archive.",
+                self.archived_only,
+            ));
+        }
+        message
+    }
+}
 
 pub struct Database {
     pool: SqlitePool,
@@ -10,7 +61,11 @@ pub struct Database {
 
 impl Database {
     pub async fn new() -> Result<Self> {
-        let db_path = Self::get_db_path()?;
+        Self::open(Self::get_db_path()?).await
+    }
+
+    /// Opens (creating if needed) and migrates the database at `db_path`.
+    pub async fn open(db_path: PathBuf) -> Result<Self> {
         let db_url = format!("sqlite://{}", db_path.display());
         
         // Create database if it doesn't exist
@@ -22,8 +77,6 @@ impl Database {
         
         // Run migrations
         Self::run_migrations(&pool).await?;
-        Self::upgrade_diary_schema_if_needed(&pool).await?;
-        Self::enforce_diary_unique_by_date(&pool).await?;
         
         Ok(Self { pool, db_path: db_path })
     }
@@ -39,131 +92,338 @@ impl Database {
     }
     
     async fn run_migrations(pool: &SqlitePool) -> Result<()> {
-        // Create tables
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS focus_sessions (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                start_time DATETIME NOT NULL,
-                end_time DATETIME,
-                duration INTEGER,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            )
-            "#
-        )
-        .execute(pool)
-        .await?;
-        
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS diary_entries (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                date DATE NOT NULL,
-                title TEXT,
-                content TEXT NOT NULL,
-                mood INTEGER,
-                images TEXT,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            )
-            "#
-        )
-        .execute(pool)
-        .await?;
-        
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS todos (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                title TEXT NOT NULL,
-                description TEXT,
-                completed BOOLEAN DEFAULT FALSE,
-                priority INTEGER DEFAULT 0,
-                due_date DATETIME,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            )
-            "#
-        )
-        .execute(pool)
-        .await?;
-        
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS alarms (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                time TIME NOT NULL,
-                days TEXT,
-                enabled BOOLEAN DEFAULT TRUE,
-                label TEXT,
-                sound_path TEXT,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            )
-            "#
-        )
-        .execute(pool)
-        .await?;
-        
+        let report = Self::adopt_legacy_schema(pool).await?;
+        MIGRATOR.run(pool).await?;
+        if !report.is_empty() {
+            let mut conn = pool.acquire().await?;
+            Self::add_startup_notice(&mut conn, "diary_merge", &report.describe()).await?;
+        }
         Ok(())
     }
-    
-    async fn upgrade_diary_schema_if_needed(pool: &SqlitePool) -> Result<()> {
-        let row: Option<(String,)> = sqlx::query_as("SELECT sql FROM sqlite_master WHERE type='table' AND name='diary_entries'")
+
+    /// Databases created before versioned migrations have no `_sqlx_migrations`
+    /// table. Bring their diary table to the shape `0001_initial_schema.sql`
+    /// expects so the baseline applies cleanly; this runs at most once.
+    async fn adopt_legacy_schema(pool: &SqlitePool) -> Result<DiaryMergeReport> {
+        let tracked: Option<(String,)> = sqlx::query_as(
+            "SELECT name FROM sqlite_master WHERE type='table' AND name='_sqlx_migrations'")
             .fetch_optional(pool)
             .await?;
-        if let Some((sql,)) = row {
-            if sql.contains("date DATE NOT NULL UNIQUE") {
-                sqlx::query(
-                    r#"
-                    CREATE TABLE IF NOT EXISTS diary_entries_new (
-                        id INTEGER PRIMARY KEY AUTOINCREMENT,
-                        date DATE NOT NULL,
-                        title TEXT,
-                        content TEXT NOT NULL,
-                        mood INTEGER,
-                        images TEXT,
-                        created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                        updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
-                    )
-                    "#
+        if tracked.is_some() {
+            return Ok(DiaryMergeReport::default());
+        }
+        let columns: Vec<(String,)> = sqlx::query_as("SELECT name FROM pragma_table_info('diary_entries')")
+            .fetch_all(pool)
+            .await?;
+        if columns.is_empty() {
+            return Ok(DiaryMergeReport::default());
+        }
+        // A UNIQUE constraint declared on the column shows up as an automatic
+        // index with origin 'u'; ones made by CREATE INDEX have origin 'c'.
+        let inline_unique: bool = sqlx::query_scalar(
+            "SELECT EXISTS(
+                SELECT 1 FROM pragma_index_list('diary_entries') AS l, pragma_index_info(l.name) AS i
+                WHERE l.origin = 'u' AND i.name = 'date')")
+            .fetch_one(pool)
+            .await?;
+
+        let mut tx = pool.begin().await?;
+        if inline_unique {
+            // The earliest releases declared the unique constraint inline; rebuild
+            // the table so uniqueness is owned by idx_diary_entries_date instead.
+            sqlx::query(
+                r#"
+                CREATE TABLE diary_entries_new (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    date DATE NOT NULL,
+                    title TEXT,
+                    content TEXT NOT NULL,
+                    mood INTEGER,
+                    images TEXT,
+                    encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+                    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
                 )
-                .execute(pool)
+                "#
+            )
+            .execute(&mut *tx)
+            .await?;
+            let encrypted = if columns.iter().any(|(name,)| name == "encrypted") { "encrypted" } else { "FALSE" };
+            sqlx::query(&format!(
+                "INSERT INTO diary_entries_new (id, date, title, content, mood, images, encrypted, created_at, updated_at)
+                 SELECT id, date, title, content, mood, images, {}, created_at, updated_at FROM diary_entries",
+                encrypted,
+            ))
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DROP TABLE diary_entries")
+                .execute(&mut *tx)
                 .await?;
-                sqlx::query(
-                    "INSERT INTO diary_entries_new (id, date, title, content, mood, images, created_at, updated_at)
-                     SELECT id, date, title, content, mood, images, created_at, updated_at FROM diary_entries"
-                )
-                .execute(pool)
+            sqlx::query("ALTER TABLE diary_entries_new RENAME TO diary_entries")
+                .execute(&mut *tx)
+                .await?;
+        } else if !columns.iter().any(|(name,)| name == "encrypted") {
+            sqlx::query("ALTER TABLE diary_entries ADD COLUMN encrypted BOOLEAN NOT NULL DEFAULT FALSE")
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        // Older builds could end up with several rows per date, which would make
+        // the baseline's unique index fail. The archive table is created ahead of
+        // its migration so the duplicates can be folded together first.
+        sqlx::query(include_str!("../migrations/0002_diary_entries_archive.sql"))
+            .execute(&mut *tx)
+            .await?;
+        let report = Self::merge_duplicate_diary_entries(&mut tx, None).await?;
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    /// Folds every date with several diary rows into its most recently updated
+    /// row: contents are concatenated oldest first, images are unioned and the
+    /// latest mood wins. Each folded row is copied to `diary_entries_archive`
+    /// before it is removed. Encrypted groups are only merged when `encryption`
+    /// is given; otherwise the older rows are archived untouched. With
+    /// `encryption`, merged and archived rows are always stored sealed.
+    pub async fn merge_duplicate_diary_entries(
+        conn: &mut SqliteConnection,
+        encryption: Option<&Encryption>,
+    ) -> Result<DiaryMergeReport> {
+        let dates: Vec<(String,)> = sqlx::query_as(
+            "SELECT date FROM diary_entries GROUP BY date HAVING COUNT(*) > 1")
+            .fetch_all(&mut *conn)
+            .await?;
+
+        let mut report = DiaryMergeReport::default();
+        for (date,) in dates {
+            let rows: Vec<MergeRow> = sqlx::query_as(
+                "SELECT id, title, content, mood, images, encrypted FROM diary_entries
+                 WHERE date = ? ORDER BY updated_at ASC, id ASC")
+                .bind(&date)
+                .fetch_all(&mut *conn)
                 .await?;
-                sqlx::query("DROP TABLE diary_entries")
-                    .execute(pool)
+            let Some(&(survivor_id, ..)) = rows.last() else { continue };
+            let losers: Vec<i64> = rows[..rows.len() - 1].iter().map(|r| r.0).collect();
+            report.dates += 1;
+
+            let cipher = encryption;
+            let merge = cipher.is_some() || !rows.iter().any(|r| r.5);
+            if merge {
+                let open = |value: &str, encrypted: bool| -> Result<String> {
+                    match (encrypted, cipher) {
+                        (true, Some(c)) => c.decrypt(value),
+                        _ => Ok(value.to_string()),
+                    }
+                };
+
+                let mut title = None;
+                let mut contents = Vec::new();
+                let mut mood = None;
+                let mut images: Vec<String> = Vec::new();
+                for (_, row_title, content, row_mood, row_images, encrypted) in &rows {
+                    if let Some(t) = row_title {
+                        title = Some(open(t, *encrypted)?);
+                    }
+                    contents.push(open(content, *encrypted)?);
+                    if row_mood.is_some() {
+                        mood = *row_mood;
+                    }
+                    let row_images: Vec<String> = row_images.as_deref()
+                        .and_then(|json| serde_json::from_str(json).ok())
+                        .unwrap_or_default();
+                    for image in row_images {
+                        if !images.contains(&image) {
+                            images.push(image);
+                        }
+                    }
+                }
+                let content = contents.join(DIARY_MERGE_SEPARATOR);
+                let images = if images.is_empty() { None } else { Some(serde_json::to_string(&images)?) };
+                let (title, content) = match cipher {
+                    Some(c) => (title.as_deref().map(|t| c.encrypt(t)).transpose()?, c.encrypt(&content)?),
+                    None => (title, content),
+                };
+
+                sqlx::query(
+                    "UPDATE diary_entries SET title = ?, content = ?, mood = ?, images = ?, encrypted = ? WHERE id = ?")
+                    .bind(title)
+                    .bind(content)
+                    .bind(mood)
+                    .bind(images)
+                    .bind(cipher.is_some())
+                    .bind(survivor_id)
+                    .execute(&mut *conn)
                     .await?;
-                sqlx::query("ALTER TABLE diary_entries_new RENAME TO diary_entries")
-                    .execute(pool)
+                report.merged += losers.len() as u64;
+            } else {
+                report.archived_only += losers.len() as u64;
+            }
+
+            for id in losers {
+                sqlx::query(
+                    "INSERT INTO diary_entries_archive
+                        (original_id, merged_into, date, title, content, mood, images, encrypted, created_at, updated_at)
+                     SELECT id, ?, date, title, content, mood, images, encrypted, created_at, updated_at
+                     FROM diary_entries WHERE id = ?")
+                    .bind(merge.then_some(survivor_id))
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+                sqlx::query("DELETE FROM diary_entries WHERE id = ?")
+                    .bind(id)
+                    .execute(&mut *conn)
                     .await?;
             }
         }
+        if let Some(cipher) = encryption {
+            Self::seal_plaintext_rows(conn, "diary_entries_archive", cipher).await?;
+        }
+
+        Ok(report)
+    }
+
+    pub async fn add_startup_notice(conn: &mut SqliteConnection, kind: &str, message: &str) -> Result<()> {
+        sqlx::query("INSERT INTO startup_notices (kind, message) VALUES (?, ?)")
+            .bind(kind)
+            .bind(message)
+            .execute(conn)
+            .await?;
         Ok(())
     }
 
-    async fn enforce_diary_unique_by_date(pool: &SqlitePool) -> Result<()> {
-        // Deduplicate keeping latest updated_at per date
-        sqlx::query(
-            r#"DELETE FROM diary_entries 
-               WHERE (date, updated_at) NOT IN (
-                 SELECT date, MAX(updated_at) FROM diary_entries GROUP BY date
-               )"#
-        )
-        .execute(pool)
-        .await?;
-        // Ensure unique index on date
+    pub async fn get_setting<'c, T, E>(executor: E, key: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+        E: Executor<'c, Database = Sqlite>,
+    {
+        let row: Option<(String,)> = sqlx::query_as("SELECT value FROM settings WHERE key = ?")
+            .bind(key)
+            .fetch_optional(executor)
+            .await?;
+        row.map(|(value,)| serde_json::from_str(&value).map_err(Into::into)).transpose()
+    }
+
+    pub async fn set_setting<'c, T, E>(executor: E, key: &str, value: &T) -> Result<()>
+    where
+        T: Serialize,
+        E: Executor<'c, Database = Sqlite>,
+    {
         sqlx::query(
-            r#"CREATE UNIQUE INDEX IF NOT EXISTS idx_diary_entries_date ON diary_entries(date)"#
-        )
-        .execute(pool)
-        .await?;
+            "INSERT INTO settings (key, value, updated_at) VALUES (?, ?, CURRENT_TIMESTAMP)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at")
+            .bind(key)
+            .bind(serde_json::to_string(value)?)
+            .execute(executor)
+            .await?;
         Ok(())
     }
+
+    pub async fn diary_mode(&self) -> Result<DiaryMode> {
+        Ok(Self::get_setting(&self.pool, DIARY_MODE_SETTING).await?.unwrap_or_default())
+    }
+
+    /// Encrypts every diary row, archived ones included, still stored in
+    /// plaintext. Returns the number of rows migrated.
+    pub async fn encrypt_plaintext_diary_entries(&self, encryption: &Encryption) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let mut migrated = 0;
+        for table in DIARY_TABLES {
+            migrated += Self::seal_plaintext_rows(&mut tx, table, encryption).await?;
+        }
+        tx.commit().await?;
+
+        Ok(migrated)
+    }
+
+    async fn seal_plaintext_rows(conn: &mut SqliteConnection, table: &str, encryption: &Encryption) -> Result<u64> {
+        let rows: Vec<(i64, Option<String>, String)> = sqlx::query_as(&format!(
+            "SELECT id, title, content FROM {} WHERE encrypted = FALSE", table))
+            .fetch_all(&mut *conn)
+            .await?;
+
+        for (id, title, content) in &rows {
+            let title = title.as_deref().map(|t| encryption.encrypt(t)).transpose()?;
+            let content = encryption.encrypt(content)?;
+            sqlx::query(&format!("UPDATE {} SET title = ?, content = ?, encrypted = TRUE WHERE id = ?", table))
+                .bind(title)
+                .bind(content)
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        Ok(rows.len() as u64)
+    }
+    
+    /// Re-encrypts every diary row, archived ones included, from `old` to `new`
+    /// in a single transaction. Plaintext rows are encrypted along the way.
+    /// Returns the number of rows written.
+    pub async fn reencrypt_diary_entries(&self, old: &Encryption, new: &Encryption) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let mut written = 0;
+        for table in DIARY_TABLES {
+            let rows: Vec<(i64, Option<String>, String, bool)> = sqlx::query_as(&format!(
+                "SELECT id, title, content, encrypted FROM {}", table))
+                .fetch_all(&mut *tx)
+                .await?;
+
+            for (id, title, content, encrypted) in &rows {
+                let (title, content) = if *encrypted {
+                    (title.as_deref().map(|t| old.decrypt(t)).transpose()?, old.decrypt(content)?)
+                } else {
+                    (title.clone(), content.clone())
+                };
+                let title = title.as_deref().map(|t| new.encrypt(t)).transpose()?;
+                let content = new.encrypt(&content)?;
+                sqlx::query(&format!("UPDATE {} SET title = ?, content = ?, encrypted = TRUE WHERE id = ?", table))
+                    .bind(title)
+                    .bind(content)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            written += rows.len() as u64;
+        }
+        tx.commit().await?;
+
+        Ok(written)
+    }
+
+    /// Finishes or rolls back a key rotation that was interrupted between
+    /// committing the re-encrypted rows and promoting the staged key.
+    pub async fn recover_interrupted_rotation(&self, current: Encryption, passphrase: Option<&str>) -> Result<Encryption> {
+        let Some(staged) = Encryption::load_staged_key(passphrase)? else {
+            return Ok(current);
+        };
+        let staged = Encryption::new(&staged)?;
+
+        if self.key_matches(&staged).await? {
+            Encryption::finish_rotation(&current, &staged)?;
+            Ok(staged)
+        } else {
+            Encryption::discard_staged_key()?;
+            Ok(current)
+        }
+    }
+    
+    /// Whether `encryption` can read the stored diary rows. Trivially true when
+    /// nothing is encrypted yet.
+    pub async fn key_matches(&self, encryption: &Encryption) -> Result<bool> {
+        let sample: Option<(String,)> = sqlx::query_as(
+            "SELECT content FROM diary_entries WHERE encrypted = TRUE
+             UNION ALL SELECT content FROM diary_entries_archive WHERE encrypted = TRUE
+             LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(match sample {
+            Some((content,)) => encryption.decrypt(&content).is_ok(),
+            None => true,
+        })
+    }
+
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
     
     pub fn pool(&self) -> &SqlitePool {
         &self.pool
@@ -172,4 +432,218 @@ impl Database {
     pub fn path(&self) -> &PathBuf {
         &self.db_path
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use sqlx::Connection;
+
+    type DiaryRow = (String, Option<String>, String, Option<i32>, Option<String>);
+
+    /// Copies a database from an older release into a scratch directory and opens it.
+    async fn open_fixture(name: &str) -> (Database, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("productivity-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("productivity.db");
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+        std::fs::copy(fixture, &path).unwrap();
+        (Database::open(path.clone()).await.unwrap(), path)
+    }
+
+    async fn diary_rows(db: &Database) -> Vec<DiaryRow> {
+        sqlx::query_as("SELECT date, title, content, mood, images FROM diary_entries ORDER BY date, id")
+            .fetch_all(db.pool())
+            .await
+            .unwrap()
+    }
+
+    async fn assert_current_schema(db: &Database) {
+        let applied: Vec<(i64, bool)> = sqlx::query_as("SELECT version, success FROM _sqlx_migrations ORDER BY version")
+            .fetch_all(db.pool())
+            .await
+            .unwrap();
+        let expected: Vec<(i64, bool)> = MIGRATOR.iter().map(|m| (m.version, true)).collect();
+        assert_eq!(applied, expected);
+
+        let columns: Vec<(String,)> = sqlx::query_as("SELECT name FROM pragma_table_info('diary_entries')")
+            .fetch_all(db.pool())
+            .await
+            .unwrap();
+        assert!(columns.iter().any(|(name,)| name == "encrypted"));
+        let inline_unique: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM pragma_index_list('diary_entries') WHERE origin = 'u')")
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert!(!inline_unique);
+
+        // The other tables come through untouched.
+        let counts: (i64, i64, i64) = sqlx::query_as(
+            "SELECT (SELECT COUNT(*) FROM todos), (SELECT COUNT(*) FROM alarms), (SELECT COUNT(*) FROM focus_sessions)")
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(counts, (1, 1, 1));
+    }
+
+    async fn notice_kinds(db: &Database) -> Vec<String> {
+        sqlx::query_scalar("SELECT kind FROM startup_notices ORDER BY id")
+            .fetch_all(db.pool())
+            .await
+            .unwrap()
+    }
+
+    fn legacy_entries() -> Vec<DiaryRow> {
+        vec![
+            ("2024-03-01".into(), Some("First".into()), "Started the diary".into(), Some(4), Some(r#"["a.png"]"#.into())),
+            ("2024-03-02".into(), None, "Rainy day".into(), Some(2), None),
+        ]
+    }
+
+    #[tokio::test]
+    async fn new_database_gets_every_migration() {
+        let path = std::env::temp_dir().join(format!("productivity-test-{}.db", uuid::Uuid::new_v4()));
+        let db = Database::open(path).await.unwrap();
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations")
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(applied as usize, MIGRATOR.iter().count());
+    }
+
+    #[tokio::test]
+    async fn adopts_inline_unique_schema() {
+        let (db, _) = open_fixture("legacy_inline_unique.db").await;
+        assert_current_schema(&db).await;
+        assert_eq!(diary_rows(&db).await, legacy_entries());
+        assert!(notice_kinds(&db).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn adopts_unique_index_schema() {
+        let (db, _) = open_fixture("legacy_unique_index.db").await;
+        assert_current_schema(&db).await;
+        assert_eq!(diary_rows(&db).await, legacy_entries());
+        assert!(notice_kinds(&db).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn adopting_duplicate_dates_merges_and_archives() {
+        let (db, path) = open_fixture("legacy_duplicate_dates.db").await;
+        assert_current_schema(&db).await;
+        assert_eq!(diary_rows(&db).await, vec![
+            (
+                "2024-03-01".into(),
+                Some("Evening".into()),
+                format!("Coffee{}Long walk", DIARY_MERGE_SEPARATOR),
+                Some(5),
+                Some(r#"["a.png","b.png"]"#.into()),
+            ),
+            ("2024-03-02".into(), None, "Rainy day".into(), Some(2), None),
+        ]);
+        let archived: Vec<(i64, Option<i64>, String)> = sqlx::query_as(
+            "SELECT original_id, merged_into, content FROM diary_entries_archive")
+            .fetch_all(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(archived, vec![(1, Some(2), "Coffee".to_string())]);
+        assert_eq!(notice_kinds(&db).await, vec!["diary_merge".to_string()]);
+
+        // Adoption happens once; reopening leaves everything as it was.
+        db.close().await;
+        let db = Database::open(path).await.unwrap();
+        assert_eq!(notice_kinds(&db).await, vec!["diary_merge".to_string()]);
+        assert_eq!(diary_rows(&db).await.len(), 2);
+    }
+
+    async fn add_diary_entry(db: &Database, date: &str, content: &str, updated_at: &str) {
+        sqlx::query("INSERT INTO diary_entries (date, content, updated_at) VALUES (?, ?, ?)")
+            .bind(date)
+            .bind(content)
+            .bind(updated_at)
+            .execute(db.pool())
+            .await
+            .unwrap();
+    }
+
+    async fn merge(db: &Database, encryption: Option<&Encryption>) -> DiaryMergeReport {
+        let mut tx = db.pool().begin().await.unwrap();
+        let report = Database::merge_duplicate_diary_entries(&mut tx, encryption).await.unwrap();
+        tx.commit().await.unwrap();
+        report
+    }
+
+    async fn insert_entry(conn: &mut SqliteConnection, date: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO diary_entries (date, content) VALUES (?, 'text')")
+            .bind(date)
+            .execute(conn)
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn one_per_day_mode_is_enforced_by_the_schema() {
+        let path = std::env::temp_dir().join(format!("productivity-test-{}.db", uuid::Uuid::new_v4()));
+        let db = Database::open(path).await.unwrap();
+        // sqlx steps a failed statement once more after reporting the error,
+        // so everything runs on one connection to keep it in order.
+        let mut conn = db.pool().acquire().await.unwrap();
+
+        // No setting yet means one per day.
+        insert_entry(&mut conn, "2024-03-01").await.unwrap();
+        assert!(insert_entry(&mut conn, "2024-03-01").await.is_err());
+
+        Database::set_setting(&mut *conn, DIARY_MODE_SETTING, &DiaryMode::ManyPerDay).await.unwrap();
+        insert_entry(&mut conn, "2024-03-01").await.unwrap();
+        assert!(Database::set_setting(&mut *conn, DIARY_MODE_SETTING, &DiaryMode::OnePerDay).await.is_err());
+
+        let mut tx = conn.begin().await.unwrap();
+        Database::merge_duplicate_diary_entries(&mut tx, None).await.unwrap();
+        tx.commit().await.unwrap();
+        Database::set_setting(&mut *conn, DIARY_MODE_SETTING, &DiaryMode::OnePerDay).await.unwrap();
+        assert!(insert_entry(&mut conn, "2024-03-01").await.is_err());
+        insert_entry(&mut conn, "2024-03-02").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn archived_entries_are_sealed_and_follow_key_rotation() {
+        let path = std::env::temp_dir().join(format!("productivity-test-{}.db", uuid::Uuid::new_v4()));
+        let db = Database::open(path).await.unwrap();
+        Database::set_setting(db.pool(), DIARY_MODE_SETTING, &DiaryMode::ManyPerDay).await.unwrap();
+
+        // Merged while no key was available, as during legacy adoption.
+        add_diary_entry(&db, "2024-03-01", "Coffee", "2024-03-01 08:00:00").await;
+        add_diary_entry(&db, "2024-03-01", "Long walk", "2024-03-01 20:00:00").await;
+        assert_eq!(merge(&db, None).await.merged, 1);
+
+        let first = Encryption::new(&Encryption::generate_key()).unwrap();
+        assert_eq!(db.encrypt_plaintext_diary_entries(&first).await.unwrap(), 2);
+
+        // Merged with the key, from plaintext rows.
+        add_diary_entry(&db, "2024-03-02", "Rain", "2024-03-02 08:00:00").await;
+        add_diary_entry(&db, "2024-03-02", "Sun", "2024-03-02 20:00:00").await;
+        assert_eq!(merge(&db, Some(&first)).await.merged, 1);
+
+        let plaintext: i64 = sqlx::query_scalar(
+            "SELECT (SELECT COUNT(*) FROM diary_entries WHERE encrypted = FALSE)
+                  + (SELECT COUNT(*) FROM diary_entries_archive WHERE encrypted = FALSE)")
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(plaintext, 0);
+
+        let second = Encryption::new(&Encryption::generate_key()).unwrap();
+        assert_eq!(db.reencrypt_diary_entries(&first, &second).await.unwrap(), 4);
+        assert!(db.key_matches(&second).await.unwrap());
+        assert!(!db.key_matches(&first).await.unwrap());
+
+        let archived: Vec<(String,)> = sqlx::query_as("SELECT content FROM diary_entries_archive ORDER BY id")
+            .fetch_all(db.pool())
+            .await
+            .unwrap();
+        let archived: Vec<String> = archived.iter().map(|(content,)| second.decrypt(content).unwrap()).collect();
+        assert_eq!(archived, vec!["Coffee", "Rain"]);
+    }
+}
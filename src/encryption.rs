@@ -3,52 +3,111 @@ use aes_gcm::{
     Aes256Gcm, Key, Nonce
 };
 use aes_gcm::AeadCore;
+use argon2::{Algorithm, Argon2, Params, Version};
 use base64::{Engine as _, engine::general_purpose};
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use directories::ProjectDirs;
 
 pub struct Encryption {
     cipher: Aes256Gcm,
+    key: Vec<u8>,
 }
 
+/// Stored in `key.header` when passphrase mode is enabled. The data key is
+/// wrapped with a key derived from the passphrase, so nothing on disk can
+/// decrypt the database without it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyHeader {
+    pub version: u32,
+    #[serde(flatten)]
+    pub params: KdfParams,
+    pub wrapped_key: String,
+}
+
+/// How a key was derived from a passphrase. Stored wherever a passphrase-derived
+/// key is used so the same key can be derived again later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub kdf: String,
+    pub salt: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+/// The backup passphrase's derived key, sealed with the data key so scheduled
+/// backups can use it without prompting. Archives record `params` and carry
+/// the data key sealed with this key, so on another machine they open with
+/// the passphrase alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BackupKeyFile {
+    params: KdfParams,
+    sealed_key: String,
+}
+
+/// Data keys replaced by rotation, each sealed with the live data key, so
+/// backups taken before a rotation can still be opened.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RetiredKeysFile {
+    sealed_keys: Vec<String>,
+}
+
+const KDF_ARGON2ID: &str = "argon2id";
+const KDF_M_COST: u32 = 64 * 1024; // KiB
+const KDF_T_COST: u32 = 3;
+const KDF_P_COST: u32 = 1;
+
 impl Encryption {
     pub fn new(key: &[u8]) -> Result<Self> {
-        let key = Key::<Aes256Gcm>::from_slice(key);
-        let cipher = Aes256Gcm::new(key);
-        Ok(Self { cipher })
+        if key.len() != 32 {
+            return Err(anyhow::anyhow!("Invalid key size"));
+        }
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+        Ok(Self { cipher, key: key.to_vec() })
     }
-    
+
     pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        Ok(general_purpose::STANDARD.encode(self.seal(plaintext.as_bytes())?))
+    }
+
+    pub fn decrypt(&self, encrypted: &str) -> Result<String> {
+        let data = general_purpose::STANDARD.decode(encrypted)?;
+        Ok(String::from_utf8(self.open(&data)?)?)
+    }
+
+    /// Encrypts raw bytes, returning `nonce || ciphertext`.
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
         let ciphertext = self.cipher
-            .encrypt(&nonce, plaintext.as_bytes())
+            .encrypt(&nonce, plaintext)
             .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
-        
+
         let mut result = nonce.to_vec();
         result.extend_from_slice(&ciphertext);
-        
-        Ok(general_purpose::STANDARD.encode(result))
+        Ok(result)
     }
-    
-    pub fn decrypt(&self, encrypted: &str) -> Result<String> {
-        let data = general_purpose::STANDARD.decode(encrypted)?;
-        
+
+    /// Inverse of [`Encryption::seal`].
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>> {
         if data.len() < 12 {
             return Err(anyhow::anyhow!("Invalid encrypted data"));
         }
-        
+
         let (nonce_bytes, ciphertext) = data.split_at(12);
         let nonce = Nonce::from_slice(nonce_bytes);
-        
-        let plaintext = self.cipher
+
+        self.cipher
             .decrypt(nonce, ciphertext)
-            .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))?;
-        
-        Ok(String::from_utf8(plaintext)?)
+            .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key
     }
-    
+
     pub fn generate_key() -> Vec<u8> {
         use rand::Rng;
         let mut key = vec![0u8; 32];
@@ -56,18 +115,374 @@ impl Encryption {
         key
     }
 
-    pub fn load_or_init_key() -> anyhow::Result<Vec<u8>> {
+    fn config_dir() -> Result<PathBuf> {
         let proj_dirs = ProjectDirs::from("com", "productivityapp", "app")
             .ok_or_else(|| anyhow::anyhow!("Failed to get project directories"))?;
-        let key_path: PathBuf = proj_dirs.config_dir().join("key.bin");
+        Ok(proj_dirs.config_dir().to_path_buf())
+    }
+
+    fn key_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("key.bin"))
+    }
+
+    fn header_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("key.header"))
+    }
+
+    // A rotated key is staged next to the live one and only promoted once the
+    // re-encryption transaction has committed.
+    fn staged_key_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("key.bin.next"))
+    }
+
+    fn staged_header_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("key.header.next"))
+    }
+
+    pub fn load_or_init_key() -> anyhow::Result<Vec<u8>> {
+        let key_path = Self::key_path()?;
         if key_path.exists() {
             let bytes = fs::read(&key_path)?;
             if bytes.len() == 32 { Ok(bytes) } else { Err(anyhow::anyhow!("Invalid key size")) }
         } else {
             let key = Self::generate_key();
-            fs::create_dir_all(proj_dirs.config_dir())?;
+            fs::create_dir_all(Self::config_dir()?)?;
             fs::write(&key_path, &key)?;
             Ok(key)
         }
     }
-}
\ No newline at end of file
+
+    pub fn passphrase_enabled() -> Result<bool> {
+        Ok(Self::header_path()?.exists())
+    }
+
+    /// Derives the wrapping key from `passphrase` and unwraps the data key.
+    pub fn unlock_key(passphrase: &str) -> Result<Vec<u8>> {
+        Self::unwrap_header(&Self::header_path()?, passphrase)
+    }
+
+    fn unwrap_header(path: &Path, passphrase: &str) -> Result<Vec<u8>> {
+        let header: KeyHeader = serde_json::from_slice(&fs::read(path)?)?;
+        let wrapping = header.params.derive(passphrase)?;
+        let wrapped = general_purpose::STANDARD.decode(&header.wrapped_key)?;
+        wrapping.open(&wrapped).map_err(|_| anyhow::anyhow!("Incorrect passphrase"))
+    }
+
+    /// Wraps `key` with `passphrase` and writes the header, then removes the
+    /// plaintext `key.bin`. Changing an existing passphrase requires
+    /// `current`, which must unwrap to `key`.
+    pub fn enable_passphrase(key: &[u8], current: Option<&str>, passphrase: &str) -> Result<()> {
+        if passphrase.is_empty() {
+            return Err(anyhow::anyhow!("Passphrase must not be empty"));
+        }
+        let header_path = Self::header_path()?;
+        Self::check_current_passphrase(&header_path, key, current)?;
+        Self::write_header(&header_path, key, passphrase, KdfParams::generate())?;
+
+        let key_path = Self::key_path()?;
+        if key_path.exists() {
+            fs::remove_file(key_path)?;
+        }
+        Ok(())
+    }
+
+    /// Passes when there is no header at `path` yet, or `current` unwraps it to `key`.
+    fn check_current_passphrase(path: &Path, key: &[u8], current: Option<&str>) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let current = current.ok_or_else(|| anyhow::anyhow!("Current passphrase is required to change it"))?;
+        if Self::unwrap_header(path, current)? != key {
+            return Err(anyhow::anyhow!("Passphrase does not match the active key"));
+        }
+        Ok(())
+    }
+
+    fn write_header(path: &Path, key: &[u8], passphrase: &str, params: KdfParams) -> Result<()> {
+        let wrapping = params.derive(passphrase)?;
+        let header = KeyHeader {
+            version: 1,
+            params,
+            wrapped_key: general_purpose::STANDARD.encode(wrapping.seal(key)?),
+        };
+        Self::write_json(path, &header)
+    }
+
+    // Write-then-rename so a crash never leaves a half-written file behind.
+    fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&tmp_path, serde_json::to_vec_pretty(value)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Persists a replacement key without activating it. With a passphrase the
+    /// key is wrapped into a staged header, otherwise it is written raw.
+    pub fn stage_key(key: &[u8], passphrase: Option<&str>) -> Result<()> {
+        match passphrase {
+            Some(passphrase) => Self::write_header(&Self::staged_header_path()?, key, passphrase, KdfParams::generate()),
+            None => {
+                fs::create_dir_all(Self::config_dir()?)?;
+                fs::write(Self::staged_key_path()?, key)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the staged key left behind by an interrupted rotation, if any.
+    pub fn load_staged_key(passphrase: Option<&str>) -> Result<Option<Vec<u8>>> {
+        match passphrase {
+            Some(passphrase) => {
+                let path = Self::staged_header_path()?;
+                if !path.exists() {
+                    return Ok(None);
+                }
+                Self::unwrap_header(&path, passphrase).map(Some)
+            }
+            None => {
+                let path = Self::staged_key_path()?;
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let bytes = fs::read(&path)?;
+                if bytes.len() == 32 { Ok(Some(bytes)) } else { Err(anyhow::anyhow!("Invalid key size")) }
+            }
+        }
+    }
+
+    /// Replaces the live key with the staged one.
+    pub fn commit_staged_key() -> Result<()> {
+        let staged_header = Self::staged_header_path()?;
+        if staged_header.exists() {
+            fs::rename(&staged_header, Self::header_path()?)?;
+        }
+        let staged_key = Self::staged_key_path()?;
+        if staged_key.exists() {
+            fs::rename(&staged_key, Self::key_path()?)?;
+        }
+        Ok(())
+    }
+
+    fn backup_key_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("backup_key.json"))
+    }
+
+    pub fn backup_passphrase_enabled() -> Result<bool> {
+        Ok(Self::backup_key_path()?.exists())
+    }
+
+    /// Sets the passphrase backups are encrypted with, or with `None` goes back
+    /// to encrypting them with the data key.
+    pub fn set_backup_passphrase(data_key: &Encryption, passphrase: Option<&str>) -> Result<()> {
+        let path = Self::backup_key_path()?;
+        match passphrase {
+            Some("") => Err(anyhow::anyhow!("Passphrase must not be empty")),
+            Some(passphrase) => {
+                let params = KdfParams::generate();
+                let backup_key = params.derive(passphrase)?;
+                let file = BackupKeyFile {
+                    params,
+                    sealed_key: general_purpose::STANDARD.encode(data_key.seal(backup_key.key())?),
+                };
+                Self::write_json(&path, &file)
+            }
+            None => {
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The backup passphrase key and how it was derived, if one is set.
+    pub fn load_backup_key(data_key: &Encryption) -> Result<Option<(KdfParams, Encryption)>> {
+        let path = Self::backup_key_path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file: BackupKeyFile = serde_json::from_slice(&fs::read(path)?)?;
+        let sealed = general_purpose::STANDARD.decode(&file.sealed_key)?;
+        let key = data_key.open(&sealed)
+            .map_err(|_| anyhow::anyhow!("Backup passphrase key is unreadable; set the backup passphrase again"))?;
+        Ok(Some((file.params, Self::new(&key)?)))
+    }
+
+    /// Re-seals the stored backup passphrase key after the data key changes.
+    pub fn reseal_backup_key(old: &Encryption, new: &Encryption) -> Result<()> {
+        let path = Self::backup_key_path()?;
+        if !path.exists() {
+            return Ok(());
+        }
+        let mut file: BackupKeyFile = serde_json::from_slice(&fs::read(&path)?)?;
+        let key = old.open(&general_purpose::STANDARD.decode(&file.sealed_key)?)?;
+        file.sealed_key = general_purpose::STANDARD.encode(new.seal(&key)?);
+        Self::write_json(&path, &file)
+    }
+
+    fn retired_keys_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("retired_keys.json"))
+    }
+
+    /// Data keys in use before the last rotations, oldest first.
+    pub fn load_retired_keys(data_key: &Encryption) -> Result<Vec<Encryption>> {
+        let path = Self::retired_keys_path()?;
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let file: RetiredKeysFile = serde_json::from_slice(&fs::read(path)?)?;
+        file.sealed_keys.iter()
+            .map(|sealed| Self::new(&data_key.open(&general_purpose::STANDARD.decode(sealed)?)?))
+            .collect()
+    }
+
+    /// Adds `old` to the retired keys and re-seals the list with `new`.
+    pub fn retire_key(old: &Encryption, new: &Encryption) -> Result<()> {
+        let mut keys = Self::load_retired_keys(old)?;
+        keys.push(Self::new(old.key())?);
+        let file = RetiredKeysFile {
+            sealed_keys: keys.iter()
+                .map(|key| Ok(general_purpose::STANDARD.encode(new.seal(key.key())?)))
+                .collect::<Result<_>>()?,
+        };
+        Self::write_json(&Self::retired_keys_path()?, &file)
+    }
+
+    /// Moves the key files over to `new` once the diary rows use it: retires
+    /// `old`, re-seals the backup passphrase key and promotes the staged key.
+    /// Steps an interrupted earlier run already did are skipped.
+    pub fn finish_rotation(old: &Encryption, new: &Encryption) -> Result<()> {
+        let retired = Self::retired_keys_path()?.exists() && Self::load_retired_keys(new).is_ok();
+        if !retired {
+            Self::retire_key(old, new)?;
+        }
+        if Self::load_backup_key(new).is_err() {
+            Self::reseal_backup_key(old, new)?;
+        }
+        Self::commit_staged_key()
+    }
+
+    pub fn discard_staged_key() -> Result<()> {
+        for path in [Self::staged_header_path()?, Self::staged_key_path()?] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KdfParams {
+    /// Fresh argon2id parameters with a random salt.
+    pub fn generate() -> Self {
+        let mut salt = [0u8; 16];
+        rand::Rng::fill(&mut rand::thread_rng(), &mut salt[..]);
+        Self {
+            kdf: KDF_ARGON2ID.to_string(),
+            salt: general_purpose::STANDARD.encode(salt),
+            m_cost: KDF_M_COST,
+            t_cost: KDF_T_COST,
+            p_cost: KDF_P_COST,
+        }
+    }
+
+    /// Derives the key for `passphrase`. Slow by design; call off the async workers.
+    pub fn derive(&self, passphrase: &str) -> Result<Encryption> {
+        if self.kdf != KDF_ARGON2ID {
+            return Err(anyhow::anyhow!("Unsupported key derivation function: {}", self.kdf));
+        }
+        let salt = general_purpose::STANDARD.decode(&self.salt)?;
+        Encryption::new(&derive_key(passphrase, &salt, self.m_cost, self.t_cost, self.p_cost)?)
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], m_cost: u32, t_cost: u32, p_cost: u32) -> Result<Vec<u8>> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32))
+        .map_err(|e| anyhow::anyhow!("Invalid key derivation parameters: {}", e))?;
+    let mut key = vec![0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("encryption-test-{}", uuid::Uuid::new_v4()))
+            .join("key.header")
+    }
+
+    /// Cheap parameters; the real ones are what makes derivation slow.
+    fn quick_params(salt: &str) -> KdfParams {
+        KdfParams {
+            kdf: KDF_ARGON2ID.to_string(),
+            salt: general_purpose::STANDARD.encode(salt),
+            m_cost: 1024,
+            t_cost: 1,
+            p_cost: 1,
+        }
+    }
+
+    #[test]
+    fn passphrase_wraps_and_unwraps_the_data_key() {
+        let path = header_path();
+        let key = Encryption::generate_key();
+        Encryption::write_header(&path, &key, "correct horse", quick_params("header-salt")).unwrap();
+
+        let header: KeyHeader = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(header.params, quick_params("header-salt"));
+        assert!(!header.wrapped_key.is_empty());
+
+        assert_eq!(Encryption::unwrap_header(&path, "correct horse").unwrap(), key);
+        let wrong = Encryption::unwrap_header(&path, "battery staple").unwrap_err();
+        assert_eq!(wrong.to_string(), "Incorrect passphrase");
+    }
+
+    #[test]
+    fn changing_the_passphrase_needs_the_current_one() {
+        let path = header_path();
+        let key = Encryption::generate_key();
+        // Nothing to check before the first passphrase is set.
+        Encryption::check_current_passphrase(&path, &key, None).unwrap();
+        Encryption::write_header(&path, &key, "old", quick_params("header-salt")).unwrap();
+
+        let missing = Encryption::check_current_passphrase(&path, &key, None).unwrap_err();
+        assert_eq!(missing.to_string(), "Current passphrase is required to change it");
+        let wrong = Encryption::check_current_passphrase(&path, &key, Some("new")).unwrap_err();
+        assert_eq!(wrong.to_string(), "Incorrect passphrase");
+        let other_key = Encryption::check_current_passphrase(&path, &Encryption::generate_key(), Some("old")).unwrap_err();
+        assert_eq!(other_key.to_string(), "Passphrase does not match the active key");
+        Encryption::check_current_passphrase(&path, &key, Some("old")).unwrap();
+    }
+
+    #[test]
+    fn new_parameters_use_argon2id_with_a_fresh_salt() {
+        let params = KdfParams::generate();
+        assert_eq!(params.kdf, "argon2id");
+        assert_eq!((params.m_cost, params.t_cost, params.p_cost), (KDF_M_COST, KDF_T_COST, KDF_P_COST));
+        assert_ne!(KdfParams::generate().salt, params.salt);
+    }
+
+    #[test]
+    fn derivation_depends_on_passphrase_and_salt() {
+        let params = quick_params("salt-one");
+        let key = params.derive("secret").unwrap();
+        assert_eq!(params.derive("secret").unwrap().key(), key.key());
+        assert_ne!(params.derive("Secret").unwrap().key(), key.key());
+        assert_ne!(quick_params("salt-two").derive("secret").unwrap().key(), key.key());
+
+        let sealed = key.seal(b"diary").unwrap();
+        assert_eq!(key.open(&sealed).unwrap(), b"diary");
+        assert!(params.derive("Secret").unwrap().open(&sealed).is_err());
+
+        let unsupported = KdfParams { kdf: "scrypt".to_string(), ..params };
+        assert!(unsupported.derive("secret").is_err());
+    }
+}
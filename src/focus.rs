@@ -0,0 +1,674 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use anyhow::Result;
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use tokio::sync::Mutex;
+use crate::database::Database;
+use crate::models::{
+    ActiveFocusSession, FocusGrouping, FocusSession, FocusSessionPause, FocusTotal, NewFocusSession, PomodoroPhase,
+};
+
+/// How often running sessions get a heartbeat.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A session whose heartbeat is at most this old at startup was running just
+/// before a quick restart and simply carries on.
+const ORPHAN_GRACE: chrono::Duration = chrono::Duration::minutes(2);
+
+/// Returned by the focus session commands. Serialized as `{ kind, message }`
+/// like the alarm errors.
+#[derive(Debug, thiserror::Error)]
+pub enum FocusError {
+    #[error("Focus session {0} is already running; end it before starting another")]
+    AlreadyActive(i64),
+    #[error("Focus session {0} not found")]
+    NotFound(i64),
+    #[error("Todo {0} not found")]
+    TodoNotFound(i64),
+    #[error("Focus session {0} has already ended")]
+    AlreadyEnded(i64),
+    #[error("Focus session {0} is already paused")]
+    AlreadyPaused(i64),
+    #[error("Focus session {0} is not paused")]
+    NotPaused(i64),
+    #[error("Focus session {0} is not orphaned")]
+    NotOrphaned(i64),
+    #[error("End time must be between the session start and now")]
+    InvalidEndTime,
+    #[error("Pomodoro phases end with the cycle; use stop_pomodoro")]
+    PomodoroPhase,
+    #[error("{0}")]
+    Storage(String),
+}
+
+impl FocusError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FocusError::AlreadyActive(_) => "already_active",
+            FocusError::NotFound(_) => "not_found",
+            FocusError::TodoNotFound(_) => "todo_not_found",
+            FocusError::AlreadyEnded(_) => "already_ended",
+            FocusError::AlreadyPaused(_) => "already_paused",
+            FocusError::NotPaused(_) => "not_paused",
+            FocusError::NotOrphaned(_) => "not_orphaned",
+            FocusError::InvalidEndTime => "invalid_end_time",
+            FocusError::PomodoroPhase => "pomodoro_phase",
+            FocusError::Storage(_) => "storage",
+        }
+    }
+}
+
+impl Serialize for FocusError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("FocusError", 2)?;
+        error.serialize_field("kind", self.kind())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
+    }
+}
+
+impl From<sqlx::Error> for FocusError {
+    fn from(e: sqlx::Error) -> Self {
+        FocusError::Storage(e.to_string())
+    }
+}
+
+/// Lets `?` pass through the `String` errors of the shared helpers, such as the locked error.
+impl From<String> for FocusError {
+    fn from(message: String) -> Self {
+        FocusError::Storage(message)
+    }
+}
+
+type FocusResult<T> = std::result::Result<T, FocusError>;
+
+pub async fn get_session(conn: &mut SqliteConnection, session_id: i64) -> FocusResult<FocusSession> {
+    sqlx::query_as::<_, FocusSession>("SELECT * FROM focus_sessions WHERE id = ?")
+        .bind(session_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(FocusError::NotFound(session_id))
+}
+
+/// The running session, plain or a Pomodoro phase. Orphaned sessions don't count.
+pub async fn active_session(conn: &mut SqliteConnection) -> FocusResult<Option<FocusSession>> {
+    let session = sqlx::query_as::<_, FocusSession>(
+        "SELECT * FROM focus_sessions WHERE end_time IS NULL AND orphaned = FALSE")
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(session)
+}
+
+/// Starts a session at `at`; `pomodoro` makes it a phase of that cycle. At
+/// most one session runs at a time, which a unique index backs up.
+pub async fn start(
+    conn: &mut SqliteConnection,
+    at: DateTime<Utc>,
+    pomodoro: Option<(i64, PomodoroPhase)>,
+    session: &NewFocusSession,
+) -> FocusResult<i64> {
+    if let Some(active) = active_session(conn).await? {
+        return Err(FocusError::AlreadyActive(active.id));
+    }
+    if let Some(todo_id) = session.todo_id {
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM todos WHERE id = ?)")
+            .bind(todo_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        if !exists {
+            return Err(FocusError::TodoNotFound(todo_id));
+        }
+    }
+    let project = session.project.as_deref().map(str::trim).filter(|project| !project.is_empty());
+    let result = sqlx::query(
+        "INSERT INTO focus_sessions (start_time, last_heartbeat, cycle_id, phase, project, todo_id)
+         VALUES (?, ?, ?, ?, ?, ?)")
+        .bind(at)
+        .bind(at)
+        .bind(pomodoro.map(|(cycle_id, _)| cycle_id))
+        .bind(pomodoro.map(|(_, phase)| phase))
+        .bind(project)
+        .bind(session.todo_id)
+        .execute(&mut *conn)
+        .await;
+    let session_id = match result {
+        Ok(result) => result.last_insert_rowid(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            let active = active_session(conn).await?.map_or(0, |session| session.id);
+            return Err(FocusError::AlreadyActive(active));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // Duplicates and blank tags are dropped.
+    let tags: BTreeSet<&str> = session.tags.iter()
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    for tag in tags {
+        sqlx::query("INSERT INTO focus_session_tags (session_id, tag) VALUES (?, ?)")
+            .bind(session_id)
+            .bind(tag)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(session_id)
+}
+
+/// Fills in the `tags` of `sessions`.
+pub async fn load_tags(conn: &mut SqliteConnection, sessions: &mut [FocusSession]) -> FocusResult<()> {
+    if sessions.is_empty() {
+        return Ok(());
+    }
+    let mut query = QueryBuilder::<Sqlite>::new("SELECT session_id, tag FROM focus_session_tags WHERE session_id IN (");
+    let mut ids = query.separated(", ");
+    for session in sessions.iter() {
+        ids.push_bind(session.id);
+    }
+    query.push(") ORDER BY tag");
+    let rows: Vec<(i64, String)> = query.build_query_as().fetch_all(&mut *conn).await?;
+
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for (session_id, tag) in rows {
+        tags.entry(session_id).or_default().push(tag);
+    }
+    for session in sessions.iter_mut() {
+        session.tags = tags.remove(&session.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+/// Focused time per project, tag or todo for sessions started between
+/// `start_date` and `end_date`, most time first.
+pub async fn totals(
+    conn: &mut SqliteConnection,
+    grouping: FocusGrouping,
+    start_date: Option<String>,
+    end_date: Option<String>,
+) -> FocusResult<Vec<FocusTotal>> {
+    let mut query = QueryBuilder::<Sqlite>::new(match grouping {
+        FocusGrouping::Project => {
+            "SELECT s.project AS key, NULL AS todo_id, SUM(s.duration) AS focused_seconds, COUNT(*) AS sessions
+             FROM focus_sessions s"
+        }
+        FocusGrouping::Tag => {
+            "SELECT t.tag AS key, NULL AS todo_id, SUM(s.duration) AS focused_seconds, COUNT(*) AS sessions
+             FROM focus_sessions s JOIN focus_session_tags t ON t.session_id = s.id"
+        }
+        FocusGrouping::Todo => {
+            "SELECT todos.title AS key, s.todo_id AS todo_id, SUM(s.duration) AS focused_seconds, COUNT(*) AS sessions
+             FROM focus_sessions s JOIN todos ON todos.id = s.todo_id"
+        }
+    });
+    query.push(" WHERE s.end_time IS NOT NULL AND (s.phase IS NULL OR s.phase = ")
+        .push_bind(PomodoroPhase::Work)
+        .push(")");
+    if let Some(start) = start_date {
+        query.push(" AND s.start_time >= ").push_bind(start);
+    }
+    if let Some(end) = end_date {
+        query.push(" AND s.start_time <= ").push_bind(end);
+    }
+    query.push(match grouping {
+        FocusGrouping::Project => " GROUP BY s.project",
+        FocusGrouping::Tag => " GROUP BY t.tag",
+        FocusGrouping::Todo => " GROUP BY s.todo_id",
+    });
+    query.push(" ORDER BY focused_seconds DESC");
+
+    let totals = query.build_query_as::<FocusTotal>().fetch_all(&mut *conn).await?;
+    Ok(totals)
+}
+
+/// The running session with its net elapsed time.
+pub async fn active(conn: &mut SqliteConnection) -> FocusResult<Option<ActiveFocusSession>> {
+    let Some(session) = active_session(conn).await? else {
+        return Ok(None);
+    };
+    let now = Utc::now();
+    let paused_at = paused_at(conn, session.id).await?;
+    let paused = paused_seconds(conn, session.id, now).await?;
+    let elapsed_seconds = ((now - session.start_time).num_seconds() - paused).max(0);
+    let mut sessions = [session];
+    load_tags(conn, &mut sessions).await?;
+    let [session] = sessions;
+    Ok(Some(ActiveFocusSession { session, elapsed_seconds, paused_at }))
+}
+
+pub async fn pauses(conn: &mut SqliteConnection, session_id: i64) -> FocusResult<Vec<FocusSessionPause>> {
+    let pauses = sqlx::query_as::<_, FocusSessionPause>(
+        "SELECT * FROM focus_session_pauses WHERE session_id = ? ORDER BY paused_at")
+        .bind(session_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(pauses)
+}
+
+/// When the running pause started, if the session is paused.
+pub async fn paused_at(conn: &mut SqliteConnection, session_id: i64) -> FocusResult<Option<DateTime<Utc>>> {
+    let paused_at = sqlx::query_scalar(
+        "SELECT paused_at FROM focus_session_pauses WHERE session_id = ? AND resumed_at IS NULL")
+        .bind(session_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(paused_at)
+}
+
+/// Seconds paused up to `until`; a pause still running counts up to `until`.
+pub async fn paused_seconds(conn: &mut SqliteConnection, session_id: i64, until: DateTime<Utc>) -> FocusResult<i64> {
+    Ok(pauses(conn, session_id).await?
+        .iter()
+        .map(|pause| (pause.resumed_at.unwrap_or(until).min(until) - pause.paused_at).num_seconds().max(0))
+        .sum())
+}
+
+pub async fn pause(conn: &mut SqliteConnection, session_id: i64, at: DateTime<Utc>) -> FocusResult<()> {
+    let session = get_session(conn, session_id).await?;
+    if session.end_time.is_some() {
+        return Err(FocusError::AlreadyEnded(session_id));
+    }
+    if paused_at(conn, session_id).await?.is_some() {
+        return Err(FocusError::AlreadyPaused(session_id));
+    }
+    sqlx::query("INSERT INTO focus_session_pauses (session_id, paused_at) VALUES (?, ?)")
+        .bind(session_id)
+        .bind(at)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn resume(conn: &mut SqliteConnection, session_id: i64, at: DateTime<Utc>) -> FocusResult<()> {
+    get_session(conn, session_id).await?;
+    let result = sqlx::query(
+        "UPDATE focus_session_pauses SET resumed_at = ? WHERE session_id = ? AND resumed_at IS NULL")
+        .bind(at)
+        .bind(session_id)
+        .execute(&mut *conn)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(FocusError::NotPaused(session_id));
+    }
+    Ok(())
+}
+
+/// Ends a session at `at`, closing a running pause, and records the net
+/// focused and paused seconds.
+pub async fn end(conn: &mut SqliteConnection, session_id: i64, at: DateTime<Utc>) -> FocusResult<()> {
+    let session = get_session(conn, session_id).await?;
+    if session.end_time.is_some() {
+        return Err(FocusError::AlreadyEnded(session_id));
+    }
+    sqlx::query("UPDATE focus_session_pauses SET resumed_at = ? WHERE session_id = ? AND resumed_at IS NULL")
+        .bind(at)
+        .bind(session_id)
+        .execute(&mut *conn)
+        .await?;
+    let paused = paused_seconds(conn, session_id, at).await?;
+    let gross = (at - session.start_time).num_seconds().max(0);
+
+    sqlx::query(
+        "UPDATE focus_sessions SET end_time = ?, duration = ?, paused_duration = ?, orphaned = FALSE WHERE id = ?")
+        .bind(at)
+        .bind((gross - paused).max(0))
+        .bind(paused)
+        .bind(session_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Keeps `last_heartbeat` of running sessions current.
+pub async fn run_heartbeat(db: Arc<Mutex<Database>>) {
+    loop {
+        {
+            let db = db.lock().await;
+            let _ = sqlx::query(
+                "UPDATE focus_sessions SET last_heartbeat = ? WHERE end_time IS NULL AND orphaned = FALSE")
+                .bind(Utc::now())
+                .execute(db.pool())
+                .await;
+        }
+        tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+    }
+}
+
+/// Deals with sessions left open by a crash or by closing the app mid-session.
+/// Those with a heartbeat end at it; those without are flagged as orphaned
+/// for the user to resolve. Pomodoro phases are left to the Pomodoro runner,
+/// which catches up on the phases that ran out meanwhile.
+pub async fn recover_orphaned_sessions(pool: &SqlitePool) -> Result<()> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    let open = sqlx::query_as::<_, FocusSession>(
+        "SELECT * FROM focus_sessions WHERE end_time IS NULL AND orphaned = FALSE AND cycle_id IS NULL")
+        .fetch_all(&mut *tx)
+        .await?;
+
+    let (mut closed, mut flagged) = (0, 0);
+    for session in open {
+        match session.last_heartbeat {
+            Some(beat) if now - beat <= ORPHAN_GRACE => continue,
+            Some(beat) => {
+                end(&mut tx, session.id, beat.max(session.start_time)).await?;
+                closed += 1;
+            }
+            None => {
+                sqlx::query("UPDATE focus_sessions SET orphaned = TRUE WHERE id = ?")
+                    .bind(session.id)
+                    .execute(&mut *tx)
+                    .await?;
+                flagged += 1;
+            }
+        }
+    }
+
+    let mut messages = Vec::new();
+    if closed > 0 {
+        messages.push(format!("{} unfinished focus session(s) were ended when the app last ran", closed));
+    }
+    if flagged > 0 {
+        messages.push(format!("{} unfinished focus session(s) need an end time", flagged));
+    }
+    if !messages.is_empty() {
+        Database::add_startup_notice(&mut tx, "orphaned_focus_sessions", &messages.join("; ")).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Ends an orphaned session at `end_time`, or deletes it when `None`.
+pub async fn resolve_orphaned(
+    conn: &mut SqliteConnection,
+    session_id: i64,
+    end_time: Option<DateTime<Utc>>,
+) -> FocusResult<()> {
+    let session = get_session(conn, session_id).await?;
+    if !session.orphaned {
+        return Err(FocusError::NotOrphaned(session_id));
+    }
+    match end_time {
+        Some(end_time) if end_time < session.start_time || end_time > Utc::now() => {
+            Err(FocusError::InvalidEndTime)
+        }
+        Some(end_time) => end(conn, session_id, end_time).await,
+        None => {
+            sqlx::query("DELETE FROM focus_sessions WHERE id = ?")
+                .bind(session_id)
+                .execute(&mut *conn)
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn database() -> Database {
+        let path = std::env::temp_dir().join(format!("productivity-test-{}.db", uuid::Uuid::new_v4()));
+        Database::open(path).await.unwrap()
+    }
+
+    /// Starts a plain session at `at` whose last heartbeat was `heartbeat`.
+    async fn start_at(pool: &SqlitePool, at: DateTime<Utc>, heartbeat: Option<DateTime<Utc>>) -> i64 {
+        let mut conn = pool.acquire().await.unwrap();
+        let id = start(&mut conn, at, None, &NewFocusSession::default()).await.unwrap();
+        sqlx::query("UPDATE focus_sessions SET last_heartbeat = ? WHERE id = ?")
+            .bind(heartbeat)
+            .bind(id)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn session(pool: &SqlitePool, id: i64) -> FocusSession {
+        get_session(&mut pool.acquire().await.unwrap(), id).await.unwrap()
+    }
+
+    async fn notices(pool: &SqlitePool) -> Vec<String> {
+        sqlx::query_scalar("SELECT message FROM startup_notices WHERE kind = 'orphaned_focus_sessions' ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn recovery_keeps_sessions_with_a_recent_heartbeat() {
+        let db = database().await;
+        let pool = db.pool();
+        let now = Utc::now();
+        let id = start_at(pool, now - chrono::Duration::hours(1), Some(now - chrono::Duration::seconds(30))).await;
+
+        recover_orphaned_sessions(pool).await.unwrap();
+        let session = session(pool, id).await;
+        assert_eq!(session.end_time, None);
+        assert!(!session.orphaned);
+        assert!(notices(pool).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recovery_ends_sessions_at_a_stale_heartbeat() {
+        let db = database().await;
+        let pool = db.pool();
+        let started = Utc::now() - chrono::Duration::hours(3);
+        let beat = started + chrono::Duration::minutes(40);
+        let id = start_at(pool, started, Some(beat)).await;
+
+        recover_orphaned_sessions(pool).await.unwrap();
+        let session = session(pool, id).await;
+        assert_eq!(session.end_time, Some(beat));
+        assert_eq!(session.duration, Some(40 * 60));
+        assert!(!session.orphaned);
+        assert_eq!(notices(pool).await, ["1 unfinished focus session(s) were ended when the app last ran"]);
+    }
+
+    #[tokio::test]
+    async fn recovery_flags_sessions_without_a_heartbeat() {
+        let db = database().await;
+        let pool = db.pool();
+        let id = start_at(pool, Utc::now() - chrono::Duration::hours(3), None).await;
+
+        recover_orphaned_sessions(pool).await.unwrap();
+        let session = session(pool, id).await;
+        assert_eq!(session.end_time, None);
+        assert!(session.orphaned);
+        assert_eq!(notices(pool).await, ["1 unfinished focus session(s) need an end time"]);
+        // A flagged session no longer blocks a new one.
+        assert!(active_session(&mut pool.acquire().await.unwrap()).await.unwrap().is_none());
+        start_at(pool, Utc::now(), Some(Utc::now())).await;
+    }
+
+    #[tokio::test]
+    async fn resolving_an_orphan_ends_or_deletes_it() {
+        let db = database().await;
+        let pool = db.pool();
+        let started = Utc::now() - chrono::Duration::hours(3);
+        let ended = start_at(pool, started, None).await;
+        recover_orphaned_sessions(pool).await.unwrap();
+        let deleted = start_at(pool, started + chrono::Duration::hours(1), None).await;
+        recover_orphaned_sessions(pool).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+
+        let too_early = resolve_orphaned(&mut conn, ended, Some(started - chrono::Duration::minutes(1))).await;
+        assert!(matches!(too_early, Err(FocusError::InvalidEndTime)));
+        let end_time = started + chrono::Duration::minutes(25);
+        resolve_orphaned(&mut conn, ended, Some(end_time)).await.unwrap();
+        let session = get_session(&mut conn, ended).await.unwrap();
+        assert_eq!(session.end_time, Some(end_time));
+        assert_eq!(session.duration, Some(25 * 60));
+        assert!(!session.orphaned);
+        let again = resolve_orphaned(&mut conn, ended, None).await;
+        assert!(matches!(again, Err(FocusError::NotOrphaned(id)) if id == ended));
+
+        resolve_orphaned(&mut conn, deleted, None).await.unwrap();
+        assert!(matches!(get_session(&mut conn, deleted).await, Err(FocusError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn only_one_session_runs_at_a_time() {
+        let db = database().await;
+        let mut conn = db.pool().acquire().await.unwrap();
+        let first = start(&mut conn, Utc::now(), None, &NewFocusSession::default()).await.unwrap();
+
+        let err = start(&mut conn, Utc::now(), None, &NewFocusSession::default()).await.unwrap_err();
+        assert!(matches!(err, FocusError::AlreadyActive(id) if id == first));
+        assert_eq!(err.kind(), "already_active");
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["kind"], "already_active");
+
+        // The index holds even when the check is bypassed.
+        let insert = sqlx::query("INSERT INTO focus_sessions (start_time) VALUES (?)")
+            .bind(Utc::now())
+            .execute(&mut *conn)
+            .await;
+        assert!(matches!(insert, Err(sqlx::Error::Database(e)) if e.is_unique_violation()));
+
+        end(&mut conn, first, Utc::now()).await.unwrap();
+        start(&mut conn, Utc::now(), None, &NewFocusSession::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn active_session_reports_net_elapsed_time() {
+        let db = database().await;
+        let mut conn = db.pool().acquire().await.unwrap();
+        assert!(active(&mut conn).await.unwrap().is_none());
+
+        let now = Utc::now();
+        let id = start(&mut conn, now - chrono::Duration::minutes(30), None, &NewFocusSession {
+            tags: vec!["deep".to_string()],
+            ..NewFocusSession::default()
+        }).await.unwrap();
+        pause(&mut conn, id, now - chrono::Duration::minutes(20)).await.unwrap();
+        resume(&mut conn, id, now - chrono::Duration::minutes(15)).await.unwrap();
+
+        let running = active(&mut conn).await.unwrap().unwrap();
+        assert_eq!(running.session.id, id);
+        assert_eq!(running.session.tags, ["deep"]);
+        assert_eq!(running.paused_at, None);
+        // A second may pass between `now` and the check.
+        assert!((25 * 60..=25 * 60 + 1).contains(&running.elapsed_seconds), "{}", running.elapsed_seconds);
+
+        let paused_at = now - chrono::Duration::minutes(5);
+        pause(&mut conn, id, paused_at).await.unwrap();
+        let running = active(&mut conn).await.unwrap().unwrap();
+        assert_eq!(running.paused_at, Some(paused_at));
+        assert!((20 * 60..=20 * 60 + 1).contains(&running.elapsed_seconds), "{}", running.elapsed_seconds);
+    }
+
+    #[tokio::test]
+    async fn duration_is_net_of_pauses() {
+        let db = database().await;
+        let mut conn = db.pool().acquire().await.unwrap();
+        let started = Utc::now() - chrono::Duration::hours(2);
+        let at = |minutes: i64| started + chrono::Duration::minutes(minutes);
+        let id = start(&mut conn, started, None, &NewFocusSession::default()).await.unwrap();
+
+        pause(&mut conn, id, at(10)).await.unwrap();
+        assert!(matches!(pause(&mut conn, id, at(11)).await, Err(FocusError::AlreadyPaused(_))));
+        resume(&mut conn, id, at(15)).await.unwrap();
+        assert!(matches!(resume(&mut conn, id, at(16)).await, Err(FocusError::NotPaused(_))));
+        pause(&mut conn, id, at(40)).await.unwrap();
+        // Ending while paused closes the pause at the end time.
+        end(&mut conn, id, at(50)).await.unwrap();
+
+        let session = get_session(&mut conn, id).await.unwrap();
+        assert_eq!(session.end_time, Some(at(50)));
+        assert_eq!(session.paused_duration, Some(15 * 60));
+        assert_eq!(session.duration, Some(35 * 60));
+        let pauses: Vec<_> = pauses(&mut conn, id).await.unwrap()
+            .into_iter()
+            .map(|pause| (pause.paused_at, pause.resumed_at))
+            .collect();
+        assert_eq!(pauses, [(at(10), Some(at(15))), (at(40), Some(at(50)))]);
+
+        assert!(matches!(end(&mut conn, id, at(60)).await, Err(FocusError::AlreadyEnded(_))));
+        assert!(matches!(pause(&mut conn, id, at(60)).await, Err(FocusError::AlreadyEnded(_))));
+    }
+
+    /// Records a session of `minutes` that started `hours_ago` hours ago.
+    async fn record(
+        conn: &mut SqliteConnection,
+        hours_ago: i64,
+        minutes: i64,
+        pomodoro: Option<(i64, PomodoroPhase)>,
+        project: Option<&str>,
+        tags: &[&str],
+        todo_id: Option<i64>,
+    ) {
+        let started = Utc::now() - chrono::Duration::hours(hours_ago);
+        let session = NewFocusSession {
+            project: project.map(str::to_string),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            todo_id,
+        };
+        let id = start(conn, started, pomodoro, &session).await.unwrap();
+        end(conn, id, started + chrono::Duration::minutes(minutes)).await.unwrap();
+    }
+
+    /// Plain sessions, one Pomodoro work phase and break, and one still running,
+    /// all but one for project "app". Returns the todo they were spent on.
+    async fn totals_fixture(conn: &mut SqliteConnection) -> i64 {
+        let todo_id = sqlx::query("INSERT INTO todos (title) VALUES ('Write report')")
+            .execute(&mut *conn)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+        let cycle_id = sqlx::query(
+            "INSERT INTO pomodoro_cycles (started_at, work_minutes, short_break_minutes, long_break_minutes, cycles_before_long_break)
+             VALUES (?, 25, 5, 15, 4)")
+            .bind(Utc::now())
+            .execute(&mut *conn)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+        record(conn, 10, 30, None, Some("app"), &["deep", "ui"], Some(todo_id)).await;
+        record(conn, 9, 20, None, Some("app"), &["deep"], None).await;
+        record(conn, 8, 10, None, None, &[], None).await;
+        record(conn, 7, 25, Some((cycle_id, PomodoroPhase::Work)), Some("app"), &["deep"], Some(todo_id)).await;
+        record(conn, 6, 5, Some((cycle_id, PomodoroPhase::ShortBreak)), Some("app"), &["deep"], Some(todo_id)).await;
+        start(conn, Utc::now(), None, &NewFocusSession {
+            project: Some("app".to_string()),
+            tags: vec!["deep".to_string()],
+            todo_id: Some(todo_id),
+        }).await.unwrap();
+        todo_id
+    }
+
+    fn summary(totals: &[FocusTotal]) -> Vec<(Option<&str>, Option<i64>, i64, i64)> {
+        totals.iter()
+            .map(|total| (total.key.as_deref(), total.todo_id, total.focused_seconds / 60, total.sessions))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn totals_by_project_leave_out_breaks() {
+        let db = database().await;
+        let mut conn = db.pool().acquire().await.unwrap();
+        totals_fixture(&mut conn).await;
+        let totals = totals(&mut conn, FocusGrouping::Project, None, None).await.unwrap();
+        assert_eq!(summary(&totals), [(Some("app"), None, 75, 3), (None, None, 10, 1)]);
+    }
+
+    #[tokio::test]
+    async fn totals_by_tag_count_each_tag() {
+        let db = database().await;
+        let mut conn = db.pool().acquire().await.unwrap();
+        totals_fixture(&mut conn).await;
+        let totals = totals(&mut conn, FocusGrouping::Tag, None, None).await.unwrap();
+        assert_eq!(summary(&totals), [(Some("deep"), None, 75, 3), (Some("ui"), None, 30, 1)]);
+    }
+
+    #[tokio::test]
+    async fn totals_by_todo_name_the_todo() {
+        let db = database().await;
+        let mut conn = db.pool().acquire().await.unwrap();
+        let todo_id = totals_fixture(&mut conn).await;
+        let totals = totals(&mut conn, FocusGrouping::Todo, None, None).await.unwrap();
+        assert_eq!(summary(&totals), [(Some("Write report"), Some(todo_id), 55, 2)]);
+    }
+}
@@ -1,17 +1,49 @@
+mod alarms;
 mod database;
 mod encryption;
+mod focus;
 mod backup;
+mod clock;
 mod commands;
 mod models;
+mod notifications;
+mod pomodoro;
+mod todos;
+mod world_clock;
 
-use tauri::Manager;
+use tauri::{Emitter, Manager};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard};
+
+/// Returned by every data command while passphrase mode is enabled and the
+/// app has not been unlocked yet.
+pub const LOCKED_ERROR: &str = "locked: unlock with your passphrase to access your data";
 
 pub struct AppState {
     db: Arc<Mutex<database::Database>>,
-    encryption: Arc<encryption::Encryption>,
+    /// `None` while locked in passphrase mode.
+    encryption: Arc<RwLock<Option<encryption::Encryption>>>,
     backup_manager: Arc<backup::BackupManager>,
+    alarm_scheduler: Arc<alarms::AlarmScheduler>,
+    notifications: Arc<notifications::NotificationService>,
+    clock: Arc<clock::ClockManager>,
+    pomodoro: Arc<pomodoro::PomodoroEngine>,
+    todo_reminders: Arc<todos::TodoReminders>,
+}
+
+impl AppState {
+    /// Locks the database for a data command, failing while the app is locked.
+    pub(crate) async fn db(&self) -> Result<MutexGuard<'_, database::Database>, String> {
+        if self.encryption.read().await.is_none() {
+            return Err(LOCKED_ERROR.to_string());
+        }
+        Ok(self.db.lock().await)
+    }
+
+    pub(crate) async fn encryption(&self) -> Result<RwLockReadGuard<'_, encryption::Encryption>, String> {
+        RwLockReadGuard::try_map(self.encryption.read().await, |e| e.as_ref())
+            .map_err(|_| LOCKED_ERROR.to_string())
+    }
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -20,11 +52,25 @@ pub fn run() {
     
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_notification::init())
         
         .invoke_handler(tauri::generate_handler![
             commands::get_focus_sessions,
+            commands::get_focus_totals,
             commands::start_focus_session,
+            commands::get_active_focus_session,
             commands::end_focus_session,
+            commands::pause_focus_session,
+            commands::resume_focus_session,
+            commands::get_focus_session_pauses,
+            commands::get_orphaned_focus_sessions,
+            commands::resolve_orphaned_session,
+            commands::get_pomodoro_settings,
+            commands::set_pomodoro_settings,
+            commands::get_pomodoro_state,
+            commands::start_pomodoro,
+            commands::skip_pomodoro_phase,
+            commands::stop_pomodoro,
             commands::get_diary_entry,
             commands::save_diary_entry,
             commands::get_diary_entries_by_month,
@@ -34,6 +80,8 @@ pub fn run() {
             commands::get_diary_entry_by_id,
             commands::update_diary_entry,
             commands::delete_diary_entry_by_id,
+            commands::get_diary_mode,
+            commands::set_diary_mode,
             commands::load_file_base64,
             commands::load_resource_file_base64,
             commands::resolve_resource_path,
@@ -45,26 +93,135 @@ pub fn run() {
             commands::create_alarm,
             commands::update_alarm,
             commands::delete_alarm,
+            commands::get_next_alarm_occurrences,
+            commands::skip_next_alarm_occurrence,
+            commands::cancel_alarm_skip,
+            commands::snooze_alarm,
+            commands::dismiss_alarm,
+            commands::get_alarm_events,
+            commands::get_alarm_snooze_defaults,
+            commands::set_alarm_snooze_defaults,
+            commands::get_timers,
+            commands::create_timer,
+            commands::start_timer,
+            commands::pause_timer,
+            commands::reset_timer,
+            commands::delete_timer,
+            commands::get_stopwatch,
+            commands::start_stopwatch,
+            commands::pause_stopwatch,
+            commands::reset_stopwatch,
+            commands::lap_stopwatch,
+            commands::get_world_clocks,
+            commands::add_world_clock,
+            commands::update_world_clock,
+            commands::delete_world_clock,
+            commands::get_time_zones,
+            commands::plan_meeting,
             commands::get_theme,
             commands::set_theme,
+            commands::get_do_not_disturb,
             commands::set_do_not_disturb,
+            commands::get_lock_status,
+            commands::unlock,
+            commands::lock,
+            commands::set_passphrase,
+            commands::rotate_encryption_key,
+            commands::get_startup_notices,
+            commands::dismiss_startup_notice,
+            commands::get_backup_status,
+            commands::trigger_backup,
+            commands::get_backup_settings,
+            commands::set_backup_settings,
+            commands::list_backups,
+            commands::verify_backup,
+            commands::restore_backup,
+            commands::preview_backup_retention,
+            commands::set_backup_passphrase,
         ])
         .setup(|app| {
-            let rt = tokio::runtime::Runtime::new()?;
-            
-            let state = rt.block_on(async {
+            // Build state on Tauri's runtime so the pool and background tasks outlive setup.
+            let state = tauri::async_runtime::block_on(async {
                 let db = database::Database::new().await?;
-                let encryption_key = encryption::Encryption::load_or_init_key()?;
-                let encryption = encryption::Encryption::new(&encryption_key)?;
+                // In passphrase mode the key only becomes available through `unlock`.
+                let encryption = if encryption::Encryption::passphrase_enabled()? {
+                    None
+                } else {
+                    let encryption_key = encryption::Encryption::load_or_init_key()?;
+                    let encryption = encryption::Encryption::new(&encryption_key)?;
+                    let encryption = db.recover_interrupted_rotation(encryption, None).await?;
+                    db.encrypt_plaintext_diary_entries(&encryption).await?;
+                    Some(encryption)
+                };
+                focus::recover_orphaned_sessions(db.pool()).await?;
                 let backup_manager = backup::BackupManager::new()?;
+                let do_not_disturb = database::Database::get_setting(db.pool(), notifications::DO_NOT_DISTURB_SETTING)
+                    .await?
+                    .unwrap_or(false);
+                let notifications = notifications::NotificationService::new(
+                    notifications::DesktopNotifier::new(app.handle().clone()),
+                    do_not_disturb,
+                );
                 
                 Ok::<AppState, anyhow::Error>(AppState {
                     db: Arc::new(Mutex::new(db)),
-                    encryption: Arc::new(encryption),
+                    encryption: Arc::new(RwLock::new(encryption)),
                     backup_manager: Arc::new(backup_manager),
+                    alarm_scheduler: Arc::new(alarms::AlarmScheduler::new()?),
+                    notifications: Arc::new(notifications),
+                    clock: Arc::new(clock::ClockManager::new()?),
+                    pomodoro: Arc::new(pomodoro::PomodoroEngine::new()),
+                    todo_reminders: Arc::new(todos::TodoReminders::new()),
                 })
             })?;
             
+            tauri::async_runtime::spawn(backup::run_schedule(
+                state.backup_manager.clone(),
+                state.db.clone(),
+                state.encryption.clone(),
+            ));
+
+            tauri::async_runtime::spawn(alarms::run_schedule(
+                state.alarm_scheduler.clone(),
+                state.db.clone(),
+                state.notifications.clone(),
+                app.handle().clone(),
+            ));
+
+            tauri::async_runtime::spawn(focus::run_heartbeat(state.db.clone()));
+
+            tauri::async_runtime::spawn(pomodoro::run_schedule(
+                state.pomodoro.clone(),
+                state.db.clone(),
+                state.notifications.clone(),
+                app.handle().clone(),
+            ));
+
+            tauri::async_runtime::spawn(todos::run_reminders(
+                state.todo_reminders.clone(),
+                state.db.clone(),
+                state.notifications.clone(),
+                app.handle().clone(),
+            ));
+
+            tauri::async_runtime::spawn(clock::run_ticker(
+                state.clock.clone(),
+                state.notifications.clone(),
+                app.handle().clone(),
+            ));
+
+            let mut warnings = state.backup_manager.subscribe_warnings();
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    match warnings.recv().await {
+                        Ok(warning) => { let _ = handle.emit("backup-warning", warning); }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
+            
             app.manage(state);
             
             #[cfg(debug_assertions)]
@@ -75,6 +232,15 @@ pub fn run() {
             }
             Ok(())
         })
-        .run(context)
-        .expect("error while running tauri application");
+        .build(context)
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Final backup on clean shutdown; skipped if nothing changed.
+                let state = app_handle.state::<AppState>();
+                let _ = tauri::async_runtime::block_on(
+                    state.backup_manager.backup_now(&state.db, &state.encryption, false),
+                );
+            }
+        });
 }
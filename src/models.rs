@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc, NaiveDate};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, Utc, Weekday};
+use std::collections::BTreeSet;
 use sqlx::FromRow;
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -7,12 +8,122 @@ pub struct FocusSession {
     pub id: i64,
     pub start_time: DateTime<Utc>,
     pub end_time: Option<DateTime<Utc>>,
+    /// Seconds focused, not counting pauses.
     pub duration: Option<i64>,
+    /// Set for the phases of a Pomodoro cycle.
+    pub cycle_id: Option<i64>,
+    pub phase: Option<PomodoroPhase>,
+    /// Seconds spent paused; `duration` plus this is the time from start to end.
+    pub paused_duration: Option<i64>,
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    /// Left open by a crash with no heartbeat to end it at; see
+    /// `resolve_orphaned_session`.
+    pub orphaned: bool,
+    pub project: Option<String>,
+    pub todo_id: Option<i64>,
+    /// Stored in `focus_session_tags`; filled in by the commands that return sessions.
+    #[sqlx(skip)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveFocusSession {
+    #[serde(flatten)]
+    pub session: FocusSession,
+    /// Seconds focused so far, not counting pauses.
+    pub elapsed_seconds: i64,
+    /// Set while paused.
+    pub paused_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct FocusSessionPause {
+    pub id: i64,
+    pub session_id: i64,
+    pub paused_at: DateTime<Utc>,
+    /// `None` while the session is paused.
+    pub resumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum PomodoroPhase {
+    Work,
+    ShortBreak,
+    LongBreak,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PomodoroSettings {
+    pub work_minutes: u32,
+    pub short_break_minutes: u32,
+    pub long_break_minutes: u32,
+    /// Work phases before the long break that ends a cycle.
+    pub cycles_before_long_break: u32,
+}
+
+impl Default for PomodoroSettings {
+    fn default() -> Self {
+        Self { work_minutes: 25, short_break_minutes: 5, long_break_minutes: 15, cycles_before_long_break: 4 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PomodoroCycle {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub work_minutes: u32,
+    pub short_break_minutes: u32,
+    pub long_break_minutes: u32,
+    pub cycles_before_long_break: u32,
+}
+
+/// The running Pomodoro phase; payload of the `pomodoro-phase-changed` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PomodoroState {
+    pub cycle_id: i64,
+    /// The focus session recording this phase.
+    pub session_id: i64,
+    pub phase: PomodoroPhase,
+    pub phase_started_at: DateTime<Utc>,
+    /// Pushed back by the time spent paused.
+    pub phase_ends_at: DateTime<Utc>,
+    /// Set while the phase is paused.
+    pub paused_at: Option<DateTime<Utc>>,
+    /// Work phases finished so far in this cycle.
+    pub completed_work_phases: u32,
+    pub cycles_before_long_break: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NewFocusSession {
-    pub start_time: DateTime<Utc>,
+    pub project: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub todo_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusGrouping {
+    Project,
+    Tag,
+    Todo,
+}
+
+/// Focused time for one project, tag or todo. Breaks between Pomodoro work
+/// phases are not counted.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct FocusTotal {
+    /// The project, tag or todo title; `None` collects sessions without a project.
+    pub key: Option<String>,
+    /// Set when grouping by todo.
+    pub todo_id: Option<i64>,
+    pub focused_seconds: i64,
+    pub sessions: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -23,10 +134,22 @@ pub struct DiaryEntry {
     pub content: String,
     pub mood: Option<i32>,
     pub images: Option<String>,
+    #[serde(skip)]
+    pub encrypted: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// Whether a date holds a single diary entry that saves update in place, or
+/// any number of entries where every save adds a new one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiaryMode {
+    #[default]
+    OnePerDay,
+    ManyPerDay,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewDiaryEntry {
     pub date: NaiveDate,
@@ -84,14 +207,71 @@ pub struct Alarm {
     pub label: Option<String>,
     pub sound_path: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// Set for one-off alarms, which ring once on this date.
+    pub date: Option<NaiveDate>,
+    /// `None` uses the app-wide [`SnoozeDefaults`].
+    pub snooze_minutes: Option<u32>,
+    pub max_snoozes: Option<u32>,
+    /// The next occurrence of a repeating alarm the user chose to skip.
+    pub skipped_occurrence: Option<DateTime<Utc>>,
+    /// JSON array of [`DateRange`]s during which the alarm stays quiet.
+    pub exceptions: Option<String>,
+}
+
+/// Payload of the `alarm-fired` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmFired {
+    pub alarm: Alarm,
+    pub scheduled_for: DateTime<Utc>,
+    /// How often this occurrence has been snoozed so far.
+    pub snoozes: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnoozeDefaults {
+    pub snooze_minutes: u32,
+    pub max_snoozes: u32,
+}
+
+impl Default for SnoozeDefaults {
+    fn default() -> Self {
+        Self { snooze_minutes: 10, max_snoozes: 3 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum AlarmEventKind {
+    Fired,
+    Snoozed,
+    Dismissed,
+    /// Rang without being snoozed or dismissed.
+    Missed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AlarmEvent {
+    pub id: i64,
+    pub alarm_id: i64,
+    pub kind: AlarmEventKind,
+    pub scheduled_for: DateTime<Utc>,
+    pub occurred_at: DateTime<Utc>,
+    pub snoozed_until: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewAlarm {
     pub time: String,
     pub days: Option<Vec<String>>,
+    /// `YYYY-MM-DD`; makes this a one-off alarm.
+    pub date: Option<String>,
     pub label: Option<String>,
     pub sound_path: Option<String>,
+    pub snooze_minutes: Option<u32>,
+    pub max_snoozes: Option<u32>,
+    pub exceptions: Option<Vec<DateRange>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -99,12 +279,313 @@ pub struct UpdateAlarm {
     pub id: i64,
     pub time: Option<String>,
     pub days: Option<Vec<String>>,
+    /// `null` turns a one-off alarm back into a repeating one.
+    #[serde(default, deserialize_with = "double_option")]
+    pub date: Option<Option<String>>,
     pub enabled: Option<bool>,
     pub label: Option<String>,
     pub sound_path: Option<String>,
+    /// `null` goes back to the app-wide default.
+    #[serde(default, deserialize_with = "double_option")]
+    pub snooze_minutes: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub max_snoozes: Option<Option<u32>>,
+    /// Replaces the existing exceptions; an empty list removes them.
+    pub exceptions: Option<Vec<DateRange>>,
+}
+
+/// Inclusive on both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DateRange {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.start <= date && date <= self.end
+    }
+}
+
+/// Tells a field set to `null` (`Some(None)`) apart from one left out (`None`).
+fn double_option<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmDay {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl From<Weekday> for AlarmDay {
+    fn from(day: Weekday) -> Self {
+        match day {
+            Weekday::Mon => AlarmDay::Mon,
+            Weekday::Tue => AlarmDay::Tue,
+            Weekday::Wed => AlarmDay::Wed,
+            Weekday::Thu => AlarmDay::Thu,
+            Weekday::Fri => AlarmDay::Fri,
+            Weekday::Sat => AlarmDay::Sat,
+            Weekday::Sun => AlarmDay::Sun,
+        }
+    }
+}
+
+/// A validated alarm time. Repeating alarms ring on `days` (every day when
+/// empty); one-off alarms ring once on `date` and have no `days`. Neither
+/// rings on dates inside `exceptions`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlarmSchedule {
+    pub time: NaiveTime,
+    pub days: BTreeSet<AlarmDay>,
+    pub date: Option<NaiveDate>,
+    pub exceptions: Vec<DateRange>,
+    /// A single occurrence that does not ring.
+    pub skipped: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmOccurrence {
+    pub alarm_id: i64,
+    pub label: Option<String>,
+    /// Local time, with the UTC offset in effect on that date.
+    pub at: DateTime<FixedOffset>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Theme {
     pub mode: String, // "light" or "dark"
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockStatus {
+    pub passphrase_enabled: bool,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct StartupNotice {
+    pub id: i64,
+    pub kind: String,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    pub enabled: bool,
+    pub interval_minutes: u32,
+    pub retention: RetentionPolicy,
+    /// Folders that each new backup is copied to, in addition to the app's own.
+    pub destinations: Vec<BackupDestination>,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_minutes: 60,
+            retention: RetentionPolicy::default(),
+            destinations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupDestination {
+    pub path: String,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+}
+
+/// A destination that was skipped or failed during a backup. Also emitted as
+/// the `backup-warning` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupWarning {
+    pub destination: String,
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+/// How many backups to keep per tier: the newest backup of each of the last
+/// `hourly` hours, `daily` days, `weekly` weeks and `monthly` months.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub hourly: u32,
+    pub daily: u32,
+    pub weekly: u32,
+    pub monthly: u32,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self { hourly: 24, daily: 7, weekly: 4, monthly: 12 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionPlan {
+    pub keep: Vec<String>,
+    pub delete: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupStatus {
+    pub in_progress: bool,
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub last_backup_path: Option<String>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub next_due_at: Option<DateTime<Utc>>,
+    /// New backups are sealed with the backup passphrase rather than the data key.
+    pub passphrase_enabled: bool,
+    /// Destinations that were skipped or failed during the latest backup.
+    pub destination_warnings: Vec<BackupWarning>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub id: String,
+    pub path: String,
+    pub size_bytes: u64,
+    pub created_at: Option<DateTime<Utc>>,
+    /// Latest applied migration; `None` for backups taken before versioned migrations.
+    pub schema_version: Option<i64>,
+    pub row_counts: std::collections::BTreeMap<String, i64>,
+    /// Version of the app that wrote the archive; `None` for raw `.db` backups.
+    pub app_version: Option<String>,
+    pub protection: BackupProtection,
+}
+
+/// What is needed to open a backup.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupProtection {
+    /// A raw `.db` copy from before backups were archived.
+    None,
+    DataKey,
+    Passphrase,
+}
+
+/// A countdown timer. Time is counted from the wall clock, so a running timer
+/// keeps counting down while the app is closed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Timer {
+    pub id: u64,
+    pub label: Option<String>,
+    pub duration_ms: i64,
+    /// Time counted down before `running_since`.
+    pub accumulated_ms: i64,
+    /// Set while running.
+    pub running_since: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimerStatus {
+    Running,
+    Paused,
+    Completed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimerSnapshot {
+    #[serde(flatten)]
+    pub timer: Timer,
+    pub status: TimerStatus,
+    pub remaining_ms: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stopwatch {
+    /// Time measured before `running_since`.
+    pub accumulated_ms: i64,
+    pub running_since: Option<DateTime<Utc>>,
+    pub laps: Vec<Lap>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lap {
+    /// Starts at 1.
+    pub number: u32,
+    /// Since the previous lap.
+    pub lap_ms: i64,
+    /// Since the stopwatch was started.
+    pub total_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StopwatchSnapshot {
+    #[serde(flatten)]
+    pub stopwatch: Stopwatch,
+    pub running: bool,
+    pub elapsed_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WorldClock {
+    pub id: i64,
+    /// IANA name, e.g. `Europe/Berlin`.
+    pub time_zone: String,
+    pub label: Option<String>,
+    pub position: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewWorldClock {
+    pub time_zone: String,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateWorldClock {
+    pub id: i64,
+    /// `null` removes the label.
+    #[serde(default, deserialize_with = "double_option")]
+    pub label: Option<Option<String>>,
+    pub position: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldClockTime {
+    #[serde(flatten)]
+    pub clock: WorldClock,
+    pub local_time: DateTime<FixedOffset>,
+    pub utc_offset_minutes: i32,
+    /// e.g. `CEST`; the numeric offset for zones without an abbreviation.
+    pub abbreviation: String,
+    pub is_daytime: bool,
+}
+
+/// A stretch of time inside everyone's working hours.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeetingWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub zones: Vec<ZoneWindow>,
+}
+
+/// A [`MeetingWindow`] as seen in one time zone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneWindow {
+    pub time_zone: String,
+    pub label: Option<String>,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+}
@@ -0,0 +1,225 @@
+use std::sync::Mutex;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+use tauri_plugin_notification::NotificationExt;
+
+pub const DO_NOT_DISTURB_SETTING: &str = "do_not_disturb";
+
+/// How many suppressed titles the summary lists before collapsing the rest.
+const SUMMARY_TITLES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Alarm,
+    TodoDue,
+    FocusSession,
+    Timer,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub title: String,
+    pub body: String,
+}
+
+/// Delivers a notification to the user.
+pub trait Notifier: Send + Sync {
+    fn show(&self, notification: &Notification) -> Result<()>;
+}
+
+/// Shows notifications through `tauri-plugin-notification`.
+pub struct DesktopNotifier {
+    app: AppHandle,
+}
+
+impl DesktopNotifier {
+    pub fn new(app: AppHandle) -> Self {
+        Self { app }
+    }
+}
+
+impl Notifier for DesktopNotifier {
+    fn show(&self, notification: &Notification) -> Result<()> {
+        self.app.notification()
+            .builder()
+            .title(&notification.title)
+            .body(&notification.body)
+            .show()
+            .map_err(|e| anyhow::anyhow!("Failed to show notification: {}", e))
+    }
+}
+
+/// Keeps every notification instead of showing it, for tests. Clones share
+/// what was recorded.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordingNotifier {
+    shown: std::sync::Arc<Mutex<Vec<Notification>>>,
+}
+
+#[cfg(test)]
+impl RecordingNotifier {
+    pub fn shown(&self) -> Vec<Notification> {
+        self.shown.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Notifier for RecordingNotifier {
+    fn show(&self, notification: &Notification) -> Result<()> {
+        self.shown.lock().unwrap().push(notification.clone());
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct DndState {
+    enabled: bool,
+    queued: Vec<Notification>,
+}
+
+/// Every backend notification goes through here. While do-not-disturb is on,
+/// notifications are queued and a single summary is shown when it turns off.
+pub struct NotificationService {
+    notifier: Box<dyn Notifier>,
+    dnd: Mutex<DndState>,
+}
+
+impl NotificationService {
+    pub fn new(notifier: impl Notifier + 'static, do_not_disturb: bool) -> Self {
+        Self {
+            notifier: Box::new(notifier),
+            dnd: Mutex::new(DndState { enabled: do_not_disturb, queued: Vec::new() }),
+        }
+    }
+
+    pub fn notify(&self, kind: NotificationKind, title: impl Into<String>, body: impl Into<String>) -> Result<()> {
+        let notification = Notification { kind, title: title.into(), body: body.into() };
+        let mut dnd = self.dnd.lock().unwrap();
+        if dnd.enabled {
+            dnd.queued.push(notification);
+            return Ok(());
+        }
+        drop(dnd);
+        self.notifier.show(&notification)
+    }
+
+    pub fn do_not_disturb(&self) -> bool {
+        self.dnd.lock().unwrap().enabled
+    }
+
+    /// Turning do-not-disturb off delivers whatever was queued meanwhile: a
+    /// single notification as it was, several as one summary.
+    pub fn set_do_not_disturb(&self, enabled: bool) -> Result<()> {
+        let queued = {
+            let mut dnd = self.dnd.lock().unwrap();
+            dnd.enabled = enabled;
+            if enabled {
+                return Ok(());
+            }
+            std::mem::take(&mut dnd.queued)
+        };
+        match queued.as_slice() {
+            [] => Ok(()),
+            [only] => self.notifier.show(only),
+            _ => self.notifier.show(&summarize(&queued)),
+        }
+    }
+}
+
+fn summarize(queued: &[Notification]) -> Notification {
+    let mut titles: Vec<&str> = queued.iter()
+        .take(SUMMARY_TITLES)
+        .map(|n| n.title.as_str())
+        .collect();
+    let more = queued.len().saturating_sub(SUMMARY_TITLES);
+    let rest = format!("and {} more", more);
+    if more > 0 {
+        titles.push(&rest);
+    }
+    Notification {
+        // The summary is shown as the kind of the first notification it covers.
+        kind: queued[0].kind,
+        title: format!("{} notifications while Do Not Disturb was on", queued.len()),
+        body: titles.join(", "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(do_not_disturb: bool) -> (NotificationService, RecordingNotifier) {
+        let recorder = RecordingNotifier::default();
+        (NotificationService::new(recorder.clone(), do_not_disturb), recorder)
+    }
+
+    fn titles(notifications: &[Notification]) -> Vec<&str> {
+        notifications.iter().map(|n| n.title.as_str()).collect()
+    }
+
+    #[test]
+    fn shows_immediately_without_do_not_disturb() {
+        let (service, recorder) = service(false);
+        service.notify(NotificationKind::Alarm, "Wake up", "7:00").unwrap();
+        assert_eq!(recorder.shown(), [Notification {
+            kind: NotificationKind::Alarm,
+            title: "Wake up".to_string(),
+            body: "7:00".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn queues_while_do_not_disturb_is_on() {
+        let (service, recorder) = service(true);
+        service.notify(NotificationKind::Timer, "Tea", "Ready").unwrap();
+        assert!(recorder.shown().is_empty());
+        // Turning it on again does not flush the queue.
+        service.set_do_not_disturb(true).unwrap();
+        assert!(recorder.shown().is_empty());
+
+        service.set_do_not_disturb(false).unwrap();
+        assert_eq!(titles(&recorder.shown()), ["Tea"]);
+        assert_eq!(recorder.shown()[0].body, "Ready");
+
+        service.notify(NotificationKind::Timer, "Pasta", "Ready").unwrap();
+        assert_eq!(titles(&recorder.shown()), ["Tea", "Pasta"]);
+    }
+
+    #[test]
+    fn turning_off_with_nothing_queued_shows_nothing() {
+        let (service, recorder) = service(true);
+        service.set_do_not_disturb(false).unwrap();
+        assert!(recorder.shown().is_empty());
+        assert!(!service.do_not_disturb());
+    }
+
+    #[test]
+    fn summarizes_several_queued_notifications() {
+        let (service, recorder) = service(false);
+        service.set_do_not_disturb(true).unwrap();
+        service.notify(NotificationKind::TodoDue, "Taxes", "Due now").unwrap();
+        service.notify(NotificationKind::Alarm, "Wake up", "7:00").unwrap();
+        service.set_do_not_disturb(false).unwrap();
+        assert_eq!(recorder.shown(), [Notification {
+            kind: NotificationKind::TodoDue,
+            title: "2 notifications while Do Not Disturb was on".to_string(),
+            body: "Taxes, Wake up".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn summary_collapses_titles_past_the_limit() {
+        let (service, recorder) = service(true);
+        for title in ["One", "Two", "Three", "Four", "Five"] {
+            service.notify(NotificationKind::Timer, title, "").unwrap();
+        }
+        service.set_do_not_disturb(false).unwrap();
+        let shown = recorder.shown();
+        assert_eq!(titles(&shown), ["5 notifications while Do Not Disturb was on"]);
+        assert_eq!(shown[0].body, "One, Two, Three, and 2 more");
+    }
+}
@@ -0,0 +1,428 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use anyhow::{bail, Result};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{Mutex, Notify};
+use crate::database::Database;
+use crate::focus::{self, FocusError};
+use crate::models::{FocusSession, NewFocusSession, PomodoroCycle, PomodoroPhase, PomodoroSettings, PomodoroState};
+use crate::notifications::{NotificationKind, NotificationService};
+
+pub const POMODORO_SETTINGS: &str = "pomodoro_settings";
+
+/// Emitted with a [`PomodoroState`] whenever a phase starts.
+pub const POMODORO_PHASE_EVENT: &str = "pomodoro-phase-changed";
+
+/// Emitted with the cycle id when a cycle ends, after its long break or when stopped.
+pub const POMODORO_FINISHED_EVENT: &str = "pomodoro-finished";
+
+pub const MAX_PHASE_MINUTES: u32 = 180;
+pub const MAX_CYCLES_BEFORE_LONG_BREAK: u32 = 12;
+
+/// Upper bound on the wait between checks, so clock changes and
+/// suspend/resume are noticed.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Wakes the runner when a command changes the cycle. The cycle itself lives
+/// in the database, so it survives webview reloads and restarts.
+#[derive(Default)]
+pub struct PomodoroEngine {
+    changed: Notify,
+}
+
+impl PomodoroEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn notify_changed(&self) {
+        self.changed.notify_one();
+    }
+}
+
+pub fn validate_settings(settings: &PomodoroSettings) -> Result<()> {
+    for (name, minutes) in [
+        ("Work", settings.work_minutes),
+        ("Short break", settings.short_break_minutes),
+        ("Long break", settings.long_break_minutes),
+    ] {
+        if minutes == 0 || minutes > MAX_PHASE_MINUTES {
+            bail!("{} length must be between 1 and {} minutes, not {}", name, MAX_PHASE_MINUTES, minutes);
+        }
+    }
+    if settings.cycles_before_long_break == 0 || settings.cycles_before_long_break > MAX_CYCLES_BEFORE_LONG_BREAK {
+        bail!(
+            "Work phases before the long break must be between 1 and {}, not {}",
+            MAX_CYCLES_BEFORE_LONG_BREAK,
+            settings.cycles_before_long_break,
+        );
+    }
+    Ok(())
+}
+
+impl PomodoroCycle {
+    fn phase_length(&self, phase: PomodoroPhase) -> chrono::Duration {
+        let minutes = match phase {
+            PomodoroPhase::Work => self.work_minutes,
+            PomodoroPhase::ShortBreak => self.short_break_minutes,
+            PomodoroPhase::LongBreak => self.long_break_minutes,
+        };
+        chrono::Duration::minutes(minutes as i64)
+    }
+
+    /// The phase after `phase`, given how many work phases are done including
+    /// it; `None` after the long break.
+    fn next_phase(&self, phase: PomodoroPhase, completed_work_phases: u32) -> Option<PomodoroPhase> {
+        match phase {
+            PomodoroPhase::Work if completed_work_phases >= self.cycles_before_long_break => Some(PomodoroPhase::LongBreak),
+            PomodoroPhase::Work => Some(PomodoroPhase::ShortBreak),
+            PomodoroPhase::ShortBreak => Some(PomodoroPhase::Work),
+            PomodoroPhase::LongBreak => None,
+        }
+    }
+}
+
+/// The running phase, if a cycle is in progress.
+pub async fn active_state(pool: &SqlitePool) -> Result<Option<PomodoroState>> {
+    let mut conn = pool.acquire().await?;
+    let Some(cycle) = sqlx::query_as::<_, PomodoroCycle>(
+        "SELECT * FROM pomodoro_cycles WHERE ended_at IS NULL ORDER BY id DESC LIMIT 1")
+        .fetch_optional(&mut *conn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Some(session) = sqlx::query_as::<_, FocusSession>(
+        "SELECT * FROM focus_sessions WHERE cycle_id = ? AND end_time IS NULL ORDER BY id DESC LIMIT 1")
+        .bind(cycle.id)
+        .fetch_optional(&mut *conn)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let completed_work_phases: u32 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM focus_sessions WHERE cycle_id = ? AND phase = ? AND end_time IS NOT NULL")
+        .bind(cycle.id)
+        .bind(PomodoroPhase::Work)
+        .fetch_one(&mut *conn)
+        .await?;
+    let phase = session.phase.unwrap_or(PomodoroPhase::Work);
+    let paused_at = focus::paused_at(&mut conn, session.id).await?;
+    let paused = focus::paused_seconds(&mut conn, session.id, Utc::now()).await?;
+
+    Ok(Some(PomodoroState {
+        cycle_id: cycle.id,
+        session_id: session.id,
+        phase,
+        phase_started_at: session.start_time,
+        phase_ends_at: session.start_time + cycle.phase_length(phase) + chrono::Duration::seconds(paused),
+        paused_at,
+        completed_work_phases,
+        cycles_before_long_break: cycle.cycles_before_long_break,
+    }))
+}
+
+async fn start_phase(tx: &mut Transaction<'_, Sqlite>, cycle_id: i64, phase: PomodoroPhase, at: DateTime<Utc>) -> Result<i64> {
+    Ok(focus::start(tx, at, Some((cycle_id, phase)), &NewFocusSession::default()).await?)
+}
+
+async fn end_cycle(tx: &mut Transaction<'_, Sqlite>, cycle_id: i64, at: DateTime<Utc>) -> Result<()> {
+    sqlx::query("UPDATE pomodoro_cycles SET ended_at = ? WHERE id = ?")
+        .bind(at)
+        .bind(cycle_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Starts a new cycle with a work phase.
+pub async fn start(pool: &SqlitePool, settings: &PomodoroSettings) -> Result<PomodoroState> {
+    validate_settings(settings)?;
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    // Every running phase has its focus session open, so one check covers both.
+    if let Some(session) = focus::active_session(&mut tx).await? {
+        if session.cycle_id.is_some() {
+            bail!("A Pomodoro cycle is already running");
+        }
+        return Err(FocusError::AlreadyActive(session.id).into());
+    }
+    let cycle_id = sqlx::query(
+        "INSERT INTO pomodoro_cycles (started_at, work_minutes, short_break_minutes, long_break_minutes, cycles_before_long_break)
+         VALUES (?, ?, ?, ?, ?)")
+        .bind(now)
+        .bind(settings.work_minutes)
+        .bind(settings.short_break_minutes)
+        .bind(settings.long_break_minutes)
+        .bind(settings.cycles_before_long_break)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+    start_phase(&mut tx, cycle_id, PomodoroPhase::Work, now).await?;
+    tx.commit().await?;
+
+    active_state(pool).await?.ok_or_else(|| anyhow::anyhow!("Pomodoro cycle {} did not start", cycle_id))
+}
+
+/// Ends the current phase at `at` and starts the next one there. Returns the
+/// new phase, or `None` when that was the long break and the cycle is over.
+async fn advance(pool: &SqlitePool, state: &PomodoroState, at: DateTime<Utc>) -> Result<Option<PomodoroState>> {
+    let cycle = sqlx::query_as::<_, PomodoroCycle>("SELECT * FROM pomodoro_cycles WHERE id = ?")
+        .bind(state.cycle_id)
+        .fetch_one(pool)
+        .await?;
+    let completed_work_phases = state.completed_work_phases + u32::from(state.phase == PomodoroPhase::Work);
+
+    let mut tx = pool.begin().await?;
+    focus::end(&mut tx, state.session_id, at).await?;
+    match cycle.next_phase(state.phase, completed_work_phases) {
+        Some(phase) => { start_phase(&mut tx, cycle.id, phase, at).await?; }
+        None => end_cycle(&mut tx, cycle.id, at).await?,
+    }
+    tx.commit().await?;
+
+    active_state(pool).await
+}
+
+/// Ends the running phase early and moves on to the next.
+pub async fn skip_phase(pool: &SqlitePool) -> Result<Option<PomodoroState>> {
+    let Some(state) = active_state(pool).await? else {
+        bail!("No Pomodoro cycle is running");
+    };
+    advance(pool, &state, Utc::now()).await
+}
+
+/// Ends the running phase and its cycle. Returns the cycle id.
+pub async fn stop(pool: &SqlitePool) -> Result<i64> {
+    let Some(state) = active_state(pool).await? else {
+        bail!("No Pomodoro cycle is running");
+    };
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    focus::end(&mut tx, state.session_id, now).await?;
+    end_cycle(&mut tx, state.cycle_id, now).await?;
+    tx.commit().await?;
+    Ok(state.cycle_id)
+}
+
+fn phase_started_message(phase: PomodoroPhase) -> (&'static str, &'static str) {
+    match phase {
+        PomodoroPhase::Work => ("Back to work", "Break's over"),
+        PomodoroPhase::ShortBreak => ("Short break", "Work phase done, take a breather"),
+        PomodoroPhase::LongBreak => ("Long break", "Last work phase of the cycle done"),
+    }
+}
+
+/// Moves the running cycle through every phase that ran out by `now`.
+/// Returns the last transition made, as the cycle id and the phase started
+/// (`None` when the cycle finished), and the phase still running afterwards.
+async fn catch_up(pool: &SqlitePool, now: DateTime<Utc>) -> (Option<(i64, Option<PomodoroState>)>, Option<PomodoroState>) {
+    let mut state = active_state(pool).await.ok().flatten();
+    let mut changed = None;
+    while let Some(current) = state.take() {
+        if current.paused_at.is_some() || current.phase_ends_at > now {
+            return (changed, Some(current));
+        }
+        let Ok(next) = advance(pool, &current, current.phase_ends_at).await else {
+            break;
+        };
+        changed = Some((current.cycle_id, next.clone()));
+        state = next;
+    }
+    (changed, None)
+}
+
+/// Moves cycles on to their next phase when the current one runs out,
+/// catching up on phases that ended while the app was closed. Only the
+/// phase reached last is announced.
+pub async fn run_schedule(
+    engine: Arc<PomodoroEngine>,
+    db: Arc<Mutex<Database>>,
+    notifications: Arc<NotificationService>,
+    app: AppHandle,
+) {
+    loop {
+        let mut wait = MAX_SLEEP;
+        {
+            let db = db.lock().await;
+            let now = Utc::now();
+            let (changed, running) = catch_up(db.pool(), now).await;
+            // Resuming wakes the loop, so a paused phase needs no timeout.
+            if let Some(running) = running.filter(|running| running.paused_at.is_none()) {
+                wait = wait.min((running.phase_ends_at - now).to_std().unwrap_or_default());
+            }
+            match changed {
+                Some((_, Some(next))) => {
+                    let (title, body) = phase_started_message(next.phase);
+                    let _ = notifications.notify(NotificationKind::FocusSession, title, body);
+                    let _ = app.emit(POMODORO_PHASE_EVENT, next);
+                }
+                Some((cycle_id, None)) => {
+                    let _ = notifications.notify(NotificationKind::FocusSession, "Pomodoro cycle complete", "Nice work");
+                    let _ = app.emit(POMODORO_FINISHED_EVENT, cycle_id);
+                }
+                None => {}
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = engine.changed.notified() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    async fn database() -> Database {
+        let path = std::env::temp_dir().join(format!("productivity-test-{}.db", uuid::Uuid::new_v4()));
+        Database::open(path).await.unwrap()
+    }
+
+    fn cycle(cycles_before_long_break: u32) -> PomodoroCycle {
+        PomodoroCycle {
+            id: 1,
+            started_at: Utc::now(),
+            ended_at: None,
+            work_minutes: 25,
+            short_break_minutes: 5,
+            long_break_minutes: 15,
+            cycles_before_long_break,
+        }
+    }
+
+    /// Phases from the first work phase to the end of the cycle.
+    fn phases(cycle: &PomodoroCycle) -> Vec<PomodoroPhase> {
+        let mut phases = vec![PomodoroPhase::Work];
+        let mut completed = 0;
+        while let Some(&phase) = phases.last() {
+            completed += u32::from(phase == PomodoroPhase::Work);
+            match cycle.next_phase(phase, completed) {
+                Some(next) => phases.push(next),
+                None => break,
+            }
+        }
+        phases
+    }
+
+    /// Starts a cycle with the default settings and moves it `ago` into the past.
+    async fn start_ago(pool: &SqlitePool, ago: Duration) -> (PomodoroState, DateTime<Utc>) {
+        let state = start(pool, &PomodoroSettings::default()).await.unwrap();
+        let started = state.phase_started_at - ago;
+        sqlx::query("UPDATE pomodoro_cycles SET started_at = ? WHERE id = ?")
+            .bind(started).bind(state.cycle_id).execute(pool).await.unwrap();
+        sqlx::query("UPDATE focus_sessions SET start_time = ? WHERE id = ?")
+            .bind(started).bind(state.session_id).execute(pool).await.unwrap();
+        (state, started)
+    }
+
+    async fn recorded_phases(pool: &SqlitePool, cycle_id: i64) -> Vec<(PomodoroPhase, DateTime<Utc>, Option<DateTime<Utc>>)> {
+        sqlx::query_as::<_, FocusSession>("SELECT * FROM focus_sessions WHERE cycle_id = ? ORDER BY id")
+            .bind(cycle_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|session| (session.phase.unwrap(), session.start_time, session.end_time))
+            .collect()
+    }
+
+    #[test]
+    fn work_alternates_with_short_breaks_until_the_long_break() {
+        use PomodoroPhase::*;
+        assert_eq!(phases(&cycle(1)), [Work, LongBreak]);
+        assert_eq!(phases(&cycle(4)), [Work, ShortBreak, Work, ShortBreak, Work, ShortBreak, Work, LongBreak]);
+    }
+
+    #[tokio::test]
+    async fn catches_up_to_the_phase_running_now() {
+        let db = database().await;
+        let pool = db.pool();
+        let (state, started) = start_ago(pool, Duration::minutes(57)).await;
+
+        let (changed, running) = catch_up(pool, started + Duration::minutes(57)).await;
+        let running = running.unwrap();
+        assert_eq!(running.phase, PomodoroPhase::ShortBreak);
+        assert_eq!(running.completed_work_phases, 2);
+        assert_eq!(running.phase_started_at, started + Duration::minutes(55));
+        assert_eq!(running.phase_ends_at, started + Duration::minutes(60));
+        assert_eq!(changed.unwrap().1.unwrap().session_id, running.session_id);
+
+        // Each phase ended where the next one started.
+        let recorded = recorded_phases(pool, state.cycle_id).await;
+        let minutes = [0, 25, 30, 55];
+        assert_eq!(recorded.len(), minutes.len());
+        for (i, (_, start_time, end_time)) in recorded.iter().enumerate() {
+            assert_eq!(*start_time, started + Duration::minutes(minutes[i]));
+            assert_eq!(*end_time, minutes.get(i + 1).map(|m| started + Duration::minutes(*m)));
+        }
+
+        let (changed, again) = catch_up(pool, started + Duration::minutes(58)).await;
+        assert!(changed.is_none());
+        assert_eq!(again.unwrap().session_id, running.session_id);
+    }
+
+    #[tokio::test]
+    async fn finishes_a_cycle_that_ran_out_while_closed() {
+        use PomodoroPhase::*;
+        let db = database().await;
+        let pool = db.pool();
+        let (state, started) = start_ago(pool, Duration::hours(5)).await;
+
+        let (changed, running) = catch_up(pool, Utc::now()).await;
+        assert_eq!(changed.map(|(cycle_id, next)| (cycle_id, next.is_none())), Some((state.cycle_id, true)));
+        assert!(running.is_none());
+        assert!(active_state(pool).await.unwrap().is_none());
+
+        let recorded = recorded_phases(pool, state.cycle_id).await;
+        let phases: Vec<_> = recorded.iter().map(|(phase, _, _)| *phase).collect();
+        assert_eq!(phases, [Work, ShortBreak, Work, ShortBreak, Work, ShortBreak, Work, LongBreak]);
+        let ended_at: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT ended_at FROM pomodoro_cycles WHERE id = ?")
+            .bind(state.cycle_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(ended_at, Some(started + Duration::minutes(130)));
+        assert_eq!(recorded.last().unwrap().2, ended_at);
+    }
+
+    #[tokio::test]
+    async fn startup_recovery_leaves_phases_to_catch_up() {
+        let db = database().await;
+        let pool = db.pool();
+        let (state, started) = start_ago(pool, Duration::hours(5)).await;
+        sqlx::query("UPDATE focus_sessions SET last_heartbeat = ? WHERE id = ?")
+            .bind(started + Duration::minutes(10))
+            .bind(state.session_id)
+            .execute(pool)
+            .await
+            .unwrap();
+
+        focus::recover_orphaned_sessions(pool).await.unwrap();
+        let running = active_state(pool).await.unwrap().unwrap();
+        assert_eq!(running.session_id, state.session_id);
+
+        let (changed, running) = catch_up(pool, Utc::now()).await;
+        assert_eq!(changed.map(|(cycle_id, next)| (cycle_id, next.is_none())), Some((state.cycle_id, true)));
+        assert!(running.is_none());
+        let ended_at: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT ended_at FROM pomodoro_cycles WHERE id = ?")
+            .bind(state.cycle_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(ended_at, Some(started + Duration::minutes(130)));
+    }
+
+    #[tokio::test]
+    async fn start_refuses_while_a_session_is_open() {
+        let db = database().await;
+        let pool = db.pool();
+        start(pool, &PomodoroSettings::default()).await.unwrap();
+        let err = start(pool, &PomodoroSettings::default()).await.unwrap_err();
+        assert_eq!(err.to_string(), "A Pomodoro cycle is already running");
+    }
+}
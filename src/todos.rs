@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use anyhow::Result;
+use sqlx::SqlitePool;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{Mutex, Notify};
+use crate::database::Database;
+use crate::models::Todo;
+use crate::notifications::{NotificationKind, NotificationService};
+
+/// Emitted with the [`Todo`] when it comes due.
+pub const TODO_DUE_EVENT: &str = "todo-due";
+
+/// Upper bound on the wait between checks, so clock changes and
+/// suspend/resume are noticed.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Wakes the reminder loop when a command adds, reschedules or deletes a todo.
+#[derive(Default)]
+pub struct TodoReminders {
+    changed: Notify,
+}
+
+impl TodoReminders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn notify_changed(&self) {
+        self.changed.notify_one();
+    }
+}
+
+/// Open todos due by `now` that have not been reminded about yet, marked as
+/// reminded in the same transaction.
+pub async fn take_due(pool: &SqlitePool, now: DateTime<Utc>) -> Result<Vec<Todo>> {
+    let mut tx = pool.begin().await?;
+    let due = sqlx::query_as::<_, Todo>(
+        "SELECT * FROM todos
+         WHERE completed = FALSE AND reminded_at IS NULL
+           AND due_date IS NOT NULL AND datetime(due_date) <= datetime(?)
+         ORDER BY due_date, id")
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await?;
+    for todo in &due {
+        sqlx::query("UPDATE todos SET reminded_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(todo.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(due)
+}
+
+/// When the next reminder is due, if any todo is still waiting for one.
+async fn next_due(pool: &SqlitePool) -> Result<Option<DateTime<Utc>>> {
+    let due: Option<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT due_date FROM todos
+         WHERE completed = FALSE AND reminded_at IS NULL AND due_date IS NOT NULL
+         ORDER BY datetime(due_date) LIMIT 1")
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+    Ok(due)
+}
+
+pub async fn run_reminders(
+    reminders: Arc<TodoReminders>,
+    db: Arc<Mutex<Database>>,
+    notifications: Arc<NotificationService>,
+    app: AppHandle,
+) {
+    loop {
+        let mut wait = MAX_SLEEP;
+        {
+            let db = db.lock().await;
+            let now = Utc::now();
+            for todo in take_due(db.pool(), now).await.unwrap_or_default() {
+                let body = todo.description.clone()
+                    .filter(|description| !description.is_empty())
+                    .unwrap_or_else(|| "Due now".to_string());
+                let _ = notifications.notify(NotificationKind::TodoDue, todo.title.clone(), body);
+                let _ = app.emit(TODO_DUE_EVENT, todo);
+            }
+            if let Ok(Some(due)) = next_due(db.pool()).await {
+                wait = wait.min((due - now).to_std().unwrap_or_default());
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = reminders.changed.notified() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    async fn database() -> Database {
+        let path = std::env::temp_dir().join(format!("productivity-test-{}.db", uuid::Uuid::new_v4()));
+        Database::open(path).await.unwrap()
+    }
+
+    async fn add_todo(pool: &SqlitePool, title: &str, due_date: Option<DateTime<Utc>>) -> i64 {
+        sqlx::query("INSERT INTO todos (title, due_date) VALUES (?, ?)")
+            .bind(title)
+            .bind(due_date)
+            .execute(pool)
+            .await
+            .unwrap()
+            .last_insert_rowid()
+    }
+
+    fn titles(todos: &[Todo]) -> Vec<&str> {
+        todos.iter().map(|todo| todo.title.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn reminds_once_for_each_open_todo_that_came_due() {
+        let db = database().await;
+        let pool = db.pool();
+        let now = Utc::now();
+        add_todo(pool, "later", Some(now + Duration::hours(1))).await;
+        add_todo(pool, "undated", None).await;
+        add_todo(pool, "due", Some(now - Duration::minutes(1))).await;
+        add_todo(pool, "overdue", Some(now - Duration::days(1))).await;
+        let done = add_todo(pool, "done", Some(now - Duration::minutes(5))).await;
+        sqlx::query("UPDATE todos SET completed = TRUE WHERE id = ?").bind(done).execute(pool).await.unwrap();
+
+        assert_eq!(titles(&take_due(pool, now).await.unwrap()), ["overdue", "due"]);
+        assert!(take_due(pool, now).await.unwrap().is_empty());
+        assert_eq!(next_due(pool).await.unwrap().map(|due| due.timestamp()), Some((now + Duration::hours(1)).timestamp()));
+        assert_eq!(titles(&take_due(pool, now + Duration::hours(2)).await.unwrap()), ["later"]);
+        assert_eq!(next_due(pool).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,218 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveTime, Offset, TimeZone, Timelike, Utc};
+use chrono_tz::{OffsetName, Tz, TZ_VARIANTS};
+use anyhow::{anyhow, bail, Result};
+use crate::models::{MeetingWindow, WorldClock, WorldClockTime, ZoneWindow};
+
+/// The bundled tz database has no coordinates, so day and night go by the
+/// local hour.
+const DAY_START_HOUR: u32 = 6;
+const NIGHT_START_HOUR: u32 = 18;
+
+/// The meeting planner checks the day in steps of this many minutes.
+const PLANNER_STEP_MINUTES: i64 = 15;
+
+pub const DEFAULT_WORK_START: &str = "09:00";
+pub const DEFAULT_WORK_END: &str = "17:00";
+
+pub fn parse_time_zone(name: &str) -> Result<Tz> {
+    name.parse().map_err(|_| anyhow!("Unknown time zone: {}", name))
+}
+
+/// `HH:MM`.
+pub fn parse_work_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| anyhow!("Invalid working hours time: {} (expected HH:MM)", time))
+}
+
+/// Every IANA name in the bundled database, for the zone picker.
+pub fn time_zone_names() -> Vec<&'static str> {
+    TZ_VARIANTS.iter().map(|tz| tz.name()).collect()
+}
+
+impl WorldClock {
+    pub fn time_at(&self, now: DateTime<Utc>) -> Result<WorldClockTime> {
+        let local = now.with_timezone(&parse_time_zone(&self.time_zone)?);
+        let offset = local.offset().fix();
+        Ok(WorldClockTime {
+            clock: self.clone(),
+            local_time: local.fixed_offset(),
+            utc_offset_minutes: offset.local_minus_utc() / 60,
+            abbreviation: local.offset().abbreviation().map_or_else(|| offset.to_string(), str::to_string),
+            is_daytime: (DAY_START_HOUR..NIGHT_START_HOUR).contains(&local.hour()),
+        })
+    }
+}
+
+/// A participant in the meeting planner.
+#[derive(Debug, Clone, Copy)]
+pub enum Zone {
+    /// The zone this computer is set to.
+    Local,
+    Named(Tz),
+}
+
+impl Zone {
+    fn at(&self, at: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Local => at.with_timezone(&Local).fixed_offset(),
+            Zone::Named(tz) => at.with_timezone(tz).fixed_offset(),
+        }
+    }
+}
+
+pub struct Participant {
+    pub zone: Zone,
+    pub time_zone: String,
+    pub label: Option<String>,
+}
+
+/// Finds the windows on `date` in `tz` of at least `min_minutes` that fall
+/// inside working hours, Monday to Friday, for every participant.
+pub fn plan_meeting<T: TimeZone>(
+    participants: &[Participant],
+    tz: &T,
+    date: NaiveDate,
+    work_start: NaiveTime,
+    work_end: NaiveTime,
+    min_minutes: i64,
+) -> Result<Vec<MeetingWindow>> {
+    if work_start >= work_end {
+        bail!("Working hours must start before they end");
+    }
+    let step = Duration::minutes(PLANNER_STEP_MINUTES);
+    let work_start = work_start.num_seconds_from_midnight() as i64 / 60;
+    let work_end = work_end.num_seconds_from_midnight() as i64 / 60;
+    let in_working_hours = |at: DateTime<FixedOffset>| {
+        let minute = at.time().num_seconds_from_midnight() as i64 / 60;
+        at.weekday().number_from_monday() <= 5
+            && minute >= work_start
+            && minute + PLANNER_STEP_MINUTES <= work_end
+    };
+
+    let local_midnight = |date: NaiveDate| {
+        tz.from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|at| at.with_timezone(&Utc))
+            .ok_or_else(|| anyhow!("{} has no midnight", date))
+    };
+    let day_end = local_midnight(date.succ_opt().ok_or_else(|| anyhow!("Date out of range"))?)?;
+
+    // (start, end) in UTC of each stretch where everyone is available.
+    let mut windows: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    let mut slot = local_midnight(date)?;
+    while slot < day_end {
+        if participants.iter().all(|p| in_working_hours(p.zone.at(slot))) {
+            match windows.last_mut() {
+                Some((_, end)) if *end == slot => *end = slot + step,
+                _ => windows.push((slot, slot + step)),
+            }
+        }
+        slot += step;
+    }
+
+    Ok(windows.into_iter()
+        .filter(|(start, end)| (*end - *start).num_minutes() >= min_minutes)
+        .map(|(start, end)| MeetingWindow {
+            start,
+            end,
+            zones: participants.iter()
+                .map(|p| ZoneWindow {
+                    time_zone: p.time_zone.clone(),
+                    label: p.label.clone(),
+                    start: p.zone.at(start),
+                    end: p.zone.at(end),
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn participant(name: &str) -> Participant {
+        Participant { zone: Zone::Named(parse_time_zone(name).unwrap()), time_zone: name.to_string(), label: None }
+    }
+
+    fn plan(names: &[&str], date: &str, min_minutes: i64) -> Vec<MeetingWindow> {
+        let participants: Vec<Participant> = names.iter().map(|name| participant(name)).collect();
+        plan_meeting(
+            &participants,
+            &Utc,
+            date.parse().unwrap(),
+            parse_work_time(DEFAULT_WORK_START).unwrap(),
+            parse_work_time(DEFAULT_WORK_END).unwrap(),
+            min_minutes,
+        ).unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn clock(time_zone: &str) -> WorldClock {
+        WorldClock { id: 1, time_zone: time_zone.to_string(), label: None, position: 0, created_at: Utc::now() }
+    }
+
+    #[test]
+    fn overlap_handles_half_hour_offsets() {
+        // Berlin is UTC+2 in June, Kolkata UTC+5:30.
+        let windows = plan(&["Europe/Berlin", "Asia/Kolkata"], "2024-06-12", 30);
+        assert_eq!(windows.len(), 1);
+        let window = &windows[0];
+        assert_eq!((window.start, window.end), (utc("2024-06-12T07:00:00Z"), utc("2024-06-12T11:30:00Z")));
+        let berlin = &window.zones[0];
+        assert_eq!(berlin.start.to_rfc3339(), "2024-06-12T09:00:00+02:00");
+        assert_eq!(berlin.end.to_rfc3339(), "2024-06-12T13:30:00+02:00");
+        let kolkata = &window.zones[1];
+        assert_eq!(kolkata.start.to_rfc3339(), "2024-06-12T12:30:00+05:30");
+        assert_eq!(kolkata.end.to_rfc3339(), "2024-06-12T17:00:00+05:30");
+
+        assert!(plan(&["Europe/Berlin", "Asia/Kolkata"], "2024-06-12", 5 * 60).is_empty());
+        assert!(plan(&["America/New_York", "Asia/Tokyo"], "2024-06-12", 15).is_empty());
+    }
+
+    #[test]
+    fn weekends_go_by_each_participants_own_day() {
+        // Saturday and Sunday in UTC.
+        assert!(plan(&["Europe/London"], "2024-06-15", 15).is_empty());
+        assert!(plan(&["Europe/London"], "2024-06-16", 15).is_empty());
+        // Sunday evening in UTC is already Monday morning in Auckland (UTC+12).
+        let windows = plan(&["Pacific/Auckland"], "2024-06-16", 15);
+        assert_eq!(windows.len(), 1);
+        assert_eq!((windows[0].start, windows[0].end), (utc("2024-06-16T21:00:00Z"), utc("2024-06-17T00:00:00Z")));
+        // And Friday evening in UTC is Saturday there.
+        let windows = plan(&["Pacific/Auckland"], "2024-06-14", 15);
+        assert_eq!(windows.len(), 1);
+        assert_eq!((windows[0].start, windows[0].end), (utc("2024-06-14T00:00:00Z"), utc("2024-06-14T05:00:00Z")));
+    }
+
+    #[test]
+    fn working_hours_must_not_be_empty() {
+        let nine = parse_work_time("09:00").unwrap();
+        assert!(plan_meeting(&[participant("UTC")], &Utc, "2024-06-12".parse().unwrap(), nine, nine, 15).is_err());
+        assert!(parse_work_time("9am").is_err());
+    }
+
+    #[test]
+    fn day_and_night_follow_the_dst_offset() {
+        let berlin = clock("Europe/Berlin");
+        // Berlin moves from CET to CEST on 2024-03-31 at 01:00 UTC.
+        let before = berlin.time_at(utc("2024-03-30T04:30:00Z")).unwrap();
+        assert_eq!((before.utc_offset_minutes, before.abbreviation.as_str()), (60, "CET"));
+        assert!(!before.is_daytime);
+        let after = berlin.time_at(utc("2024-03-31T04:30:00Z")).unwrap();
+        assert_eq!((after.utc_offset_minutes, after.abbreviation.as_str()), (120, "CEST"));
+        assert!(after.is_daytime);
+
+        // And back on 2024-10-27 at 01:00 UTC.
+        assert!(!berlin.time_at(utc("2024-10-26T16:30:00Z")).unwrap().is_daytime);
+        assert!(berlin.time_at(utc("2024-10-27T16:30:00Z")).unwrap().is_daytime);
+
+        let kolkata = clock("Asia/Kolkata").time_at(utc("2024-06-12T00:30:00Z")).unwrap();
+        assert_eq!(kolkata.utc_offset_minutes, 330);
+        assert_eq!(kolkata.local_time.to_rfc3339(), "2024-06-12T06:00:00+05:30");
+        assert!(kolkata.is_daytime);
+    }
+}